@@ -1,71 +1,202 @@
 use std::cmp::min;
+use std::env;
 use std::fmt::{Display, Formatter};
 use std::io::{ErrorKind, Write};
 use std::iter::Iterator;
 use std::marker::PhantomData;
-use std::str;
-use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::mem;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use async_trait::async_trait;
 use crossterm::{ExecutableCommand, execute, queue, QueueableCommand};
 use crossterm::cursor::{MoveToColumn, MoveUp};
+use crossterm::event::{DisableBracketedPaste, EnableBracketedPaste};
 use crossterm::style::{Color, Print, StyledContent, Stylize};
 use crossterm::style::Color::Reset;
 use crossterm::terminal::{Clear, DisableLineWrap, EnableLineWrap, SetTitle};
 use crossterm::terminal::ClearType::{CurrentLine, FromCursorDown};
-use tokio::sync::mpsc::{Receiver, unbounded_channel, UnboundedReceiver, UnboundedSender};
-use tokio::task::{JoinHandle, spawn_blocking};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::task::JoinHandle;
 use tokio::time::timeout;
+use unicode_segmentation::UnicodeSegmentation;
+use tracing::{info, info_span, Instrument, warn};
+use unicode_width::UnicodeWidthStr;
 
-use MenuOptions::{Gallery, Submit};
+use MenuOptions::{Gallery, Review, Submit, Watch};
 
 use crate::{SharedTerminalParams, TerminalCode};
 use crate::app::MenuOptions::Info;
-use crate::app::TerminalHandleMsg::{Data, Flush};
-use crate::AsciiCode::{ArrowDown, ArrowUp, Backspace, Char, Enter, EoT};
-use crate::database::{FormData, SubmissionsAirtableBase};
-use crate::ssh_client::SSHForwardingSession;
+use crate::app::TerminalHandleMsg::{Data, PauseRecording, StartRecording};
+use crate::AsciiCode::{ArrowDown, ArrowLeft, ArrowRight, ArrowUp, Backspace, Char, Ctrl, Delete, End, Enter, EoT, Esc, Home, Paste};
+use crate::database::{FormData, Record, SubmissionStore};
+use crate::limits::{minutes, LimitReached, Permit, SandboxSlot, LIMITS};
+use crate::live::{LiveEvent, LiveSession};
+use crate::metrics::METRICS;
+use crate::notices::{self, Notice};
+use crate::shutdown;
+use crate::recording::Recording;
+use crate::route::Route;
+use crate::ssh_client::{self, shell_quote, SSHForwardingSession, SANDBOX_HOST, SANDBOX_KEY, SANDBOX_USER};
+use crate::storage::{Identity, LocalStore};
+
+/// Where an [`App`]'s output ends up, e.g. an SSH channel or the local terminal.
+#[async_trait]
+pub trait OutputSink: Send + 'static {
+    async fn send(&mut self, data: Vec<u8>) -> std::io::Result<()>;
+}
 
 enum TerminalHandleMsg {
-    Flush,
     Data(Vec<u8>),
+    StartRecording(Recording),
+    PauseRecording(bool)
 }
 
-struct AsyncWriter<Out: Write+Send+'static> {
-    sender: Option<UnboundedSender<TerminalHandleMsg>>,
+// frames waiting to go out before writers have to wait for the client to catch up
+const OUTPUT_QUEUE: usize = 32;
+const MAX_BATCH_BYTES: usize = 64 * 1024;
+// most drawing doesn't wait for the client, so a client that stops reading is only noticed once
+// this much is waiting for it. it's far more than any screen the app draws
+const MAX_BACKLOG_BYTES: usize = 1024 * 1024;
+// installing a crate is most of a check, and it's stopped in the sandbox well before this
+const CHECK_TIMEOUT: Duration = Duration::from_secs(20 * 60);
+
+/// Buffers writes and hands each flushed frame to a task that sends it on to `Out`.
+///
+/// `Write` can't wait, so frames that don't fit in the queue pile up in `backlog` until
+/// [`Self::ready`] is awaited. That's where a slow client slows down whoever is writing. Writers
+/// that never wait get an error instead, once the backlog passes [`MAX_BACKLOG_BYTES`].
+pub struct AsyncWriter<Out: OutputSink> {
+    sender: Option<Sender<TerminalHandleMsg>>,
     worker: Option<JoinHandle<()>>, // auto-exited when sender is dropped
 
+    // written but not flushed yet
+    buffer: Vec<u8>,
+    // flushed but not queued yet
+    backlog: Vec<u8>,
+
     _phantom_out: PhantomData<Out>
 }
 
-impl<Out: Write+Send> AsyncWriter<Out> {
-    fn new(out: Out) -> Self {
-        let (send, recv) = unbounded_channel::<TerminalHandleMsg>();
+impl<Out: OutputSink> AsyncWriter<Out> {
+    pub fn new(out: Out) -> Self {
+        let (send, recv) = channel::<TerminalHandleMsg>(OUTPUT_QUEUE);
         Self {
             sender: Some(send),
             worker: Some(tokio::spawn(Self::worker(recv, out))),
 
+            buffer: Vec::new(),
+            backlog: Vec::new(),
+
             _phantom_out: PhantomData
         }
     }
 
-    async fn worker(mut recv: UnboundedReceiver<TerminalHandleMsg>, out: Out) {
-        let shared_out  = Arc::new(Mutex::new(out));
-        while let Some(msg) = recv.recv().await {
-            let out = shared_out.clone();
-            spawn_blocking(move ||
-            match msg {
-                Data(c) => {
-                    let _ = out.lock().unwrap().write(c.as_slice()).unwrap();
+    async fn worker(mut recv: Receiver<TerminalHandleMsg>, mut out: Out) {
+        let mut recording = None::<Recording>;
+        let mut recording_paused = false;
+        let mut notices = notices::subscribe();
+        let mut next = None;
+
+        loop {
+            let msg = match next.take() {
+                Some(msg) => msg,
+                None => tokio::select! {
+                    msg = recv.recv() => match msg {
+                        Some(msg) => msg,
+                        None => break
+                    },
+                    // the queue only ever holds whole frames, so a notice can go between any two
+                    Ok(notice) = notices.recv() => Data(Self::banner(&notice))
+                }
+            };
+
+            let mut batch = match msg {
+                Data(data) => data,
+                StartRecording(new_recording) => {
+                    recording = Some(new_recording);
+                    continue;
                 }
-                Flush => {
-                    out.lock().unwrap().flush().unwrap();
+                PauseRecording(paused) => {
+                    recording_paused = paused;
+                    continue;
                 }
-            }).await.unwrap();
+            };
+
+            // send whatever else has piled up in the same packet
+            while batch.len() < MAX_BATCH_BYTES {
+                match recv.try_recv() {
+                    Ok(Data(data)) => batch.extend(data),
+                    Ok(msg) => {
+                        next = Some(msg);
+                        break;
+                    }
+                    Err(_) => break
+                }
+            }
+
+            let recorded = recording.as_mut().filter(|_| !recording_paused);
+            if let Some(Err(e)) = recorded.map(|recording| recording.output(batch.as_slice())) {
+                warn!(error = %e, "stopping recording");
+                recording = None;
+            }
+
+            // the client has gone away. stopping closes the queue, so the next write fails and
+            // whoever's writing finds out
+            if let Err(e) = out.send(batch).await {
+                info!(error = %e, "output closed");
+                break;
+            }
         }
     }
 
-    async fn wait(&mut self) {
+    /// Draws a notice over the top row of the screen, then puts the cursor back where it was so
+    /// whatever prompt is open carries on undisturbed.
+    fn banner(notice: &Notice) -> Vec<u8> {
+        let text_box = App::<Out, fn()>::text_box(notice.message.as_str().white().bold(), Color::DarkMagenta, 0, 1, 0);
+
+        format!("\x1b7\x1b[1;1H\x1b[2K{}\x1b8", text_box.trim_end_matches("\r\n")).into_bytes()
+    }
+
+    /// Waits until everything flushed so far is queued to be sent.
+    pub async fn ready(&mut self) -> std::io::Result<()> {
+        if !self.backlog.is_empty() {
+            let backlog = mem::take(&mut self.backlog);
+            self.sender.as_ref().unwrap().send(Data(backlog)).await
+                .map_err(|_| std::io::Error::other("Send Error"))?;
+        }
+
+        Ok(())
+    }
+
+    /// Tees everything written from now on into `recording`.
+    async fn record(&mut self, recording: Recording) {
+        let _ = self.flush();
+        let _ = self.ready().await;
+        let _ = self.sender.as_ref().unwrap().send(StartRecording(recording)).await;
+    }
+
+    /// Leaves everything written from now on out of the recording (if there is one), until
+    /// [`Self::resume_recording`].
+    async fn pause_recording(&mut self) {
+        let _ = self.flush();
+        let _ = self.ready().await;
+        let _ = self.sender.as_ref().unwrap().send(PauseRecording(true)).await;
+    }
+
+    async fn resume_recording(&mut self) {
+        let _ = self.flush();
+        let _ = self.ready().await;
+        let _ = self.sender.as_ref().unwrap().send(PauseRecording(false)).await;
+    }
+
+    /// Sends everything that's left and waits for the worker to finish.
+    pub async fn wait(&mut self) {
+        let _ = self.flush();
+        let _ = self.ready().await;
+
         if let Some(worker) = self.worker.take() {
             drop(self.sender.take());
             worker.await.unwrap() 
@@ -73,39 +204,56 @@ impl<Out: Write+Send> AsyncWriter<Out> {
     }
 }
 
-impl<Out: Write+Send> Write for AsyncWriter<Out> {
+impl<Out: OutputSink> Write for AsyncWriter<Out> {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        self.sender.as_mut().unwrap().send(Data(Vec::from(buf))).unwrap();
+        self.buffer.extend_from_slice(buf);
 
         Ok(buf.len())
     }
 
     fn flush(&mut self) -> std::io::Result<()> {
-        self.sender.as_mut().unwrap().send(Flush)
-            .map_err(|_| std::io::Error::new(ErrorKind::Other, "Send Error"))
+        self.backlog.append(&mut self.buffer);
+        if self.backlog.is_empty() {
+            return Ok(());
+        }
+
+        match self.sender.as_ref().unwrap().try_send(Data(mem::take(&mut self.backlog))) {
+            Ok(()) => Ok(()),
+            // the queue is full, so hold on to it (and anything flushed after it) until `ready`
+            Err(TrySendError::Full(Data(frame))) if frame.len() <= MAX_BACKLOG_BYTES => {
+                self.backlog = frame;
+                Ok(())
+            }
+            Err(TrySendError::Full(_)) => Err(std::io::Error::new(ErrorKind::TimedOut, "the client stopped reading output")),
+            Err(_) => Err(std::io::Error::other("Send Error"))
+        }
     }
 }
 
-pub struct App<Out: Write+Send+'static, F> where F: FnOnce() {
+pub struct App<Out: OutputSink, F> where F: FnOnce() {
     out: AsyncWriter<Out>,
     input: Receiver<TerminalCode>,
     params: SharedTerminalParams,
-    
+    store: Arc<dyn SubmissionStore>,
+    local: Arc<LocalStore>,
+
     exit_fn_once: Option<F>
 }
 
-impl<Out: Write+Send, F> App<Out, F> where F: FnOnce() {
-    pub fn new(out: Out, input: Receiver<TerminalCode>, params: SharedTerminalParams, exit: F) -> Self {
+impl<Out: OutputSink, F> App<Out, F> where F: FnOnce() {
+    pub fn new(out: Out, input: Receiver<TerminalCode>, params: SharedTerminalParams, store: Arc<dyn SubmissionStore>, local: Arc<LocalStore>, exit: F) -> Self {
         let writer = AsyncWriter::new(out);
-        Self {out: writer, input, params, exit_fn_once: Some(exit)}
+        Self {out: writer, input, params, store, local, exit_fn_once: Some(exit)}
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 enum MenuOptions {
     Info,
     Submit,
-    Gallery
+    Gallery,
+    Watch,
+    Review
 }
 
 impl Display for MenuOptions {
@@ -113,18 +261,57 @@ impl Display for MenuOptions {
         write!(f, "{}", match self {
             Info => "What is this?\r\n  (+ resources & criteria)",
             Submit => "Submit your project",
-            Gallery => "See the gallery"
+            Gallery => "See the gallery",
+            Watch => "Watch or join a live session",
+            Review => "Review submissions (admin)"
         })
     }
 }
 
-impl<Out: Write+Send, F> App<Out, F> where F: FnOnce() {
-    pub async fn run(&mut self) -> std::io::Result<()> {
-        self.menu().await?;
+impl<Out: OutputSink, F> App<Out, F> where F: FnOnce() {
+    /// Starts the app at `route` (usually the menu), and exits once the user is done there.
+    pub async fn route(&mut self, route: Route) -> std::io::Result<()> {
+        self.start_recording().await;
+        self.out.execute(EnableBracketedPaste)?;
+        self.out.execute(SetTitle("cargo cult"))?;
+        info!(?route, "starting");
+
+        match route {
+            Route::Menu => self.menu().await?,
+            Route::Gallery => self.gallery().await?,
+            Route::Submit => self.submission_form().await?,
+            Route::Try { package_name } => self.try_project(&package_name).await?,
+            Route::Watch { code } => self.watch_code(&code).await?,
+            Route::Readme { package_name } => self.readme(&package_name).await?
+        }
+
         self.exit().await;
     }
+
+    /// What a connection over the server's limits sees instead of the menu.
+    pub async fn refuse(&mut self, limit: LimitReached) -> ! {
+        let _ = self.show_limit(&limit);
+        self.exit().await;
+    }
+
+    fn show_limit(&mut self, limit: &LimitReached) -> std::io::Result<()> {
+        self.newline()?;
+        self.println(Self::text_box(limit.reason.white().bold(), Color::DarkRed, 1, 3, 2))?;
+        self.println(format!("  Please try again in {}.", limit.retry_after_text()).bold())
+    }
     
+    async fn start_recording(&mut self) {
+        let params = self.params.lock().await.clone();
+
+        match Recording::start("tui", &params) {
+            Ok(Some(recording)) => self.out.record(recording).await,
+            Ok(None) => {}
+            Err(e) => warn!(error = %e, "couldn't start recording")
+        }
+    }
+
     async fn exit(&mut self) -> ! {
+        let _ = self.out.execute(DisableBracketedPaste);
         self.out.wait().await;
         if let Some(exit) = self.exit_fn_once.take() {
             exit()
@@ -152,14 +339,19 @@ impl<Out: Write+Send, F> App<Out, F> where F: FnOnce() {
     }
 
     async fn menu(&mut self) -> std::io::Result<()> {
-        self.out.execute(SetTitle("cargo cult"))?;
-
         self.slow_print(Self::ferris_ascii_art()).await?;
         self.println(Self::text_box("Welcome to the Cargo Cult!".white().bold(), Color::DarkRed, 1, 3, 2))?;
 
-        let options = &[Info, Gallery, Submit];
+        let mut options = vec![Info, Gallery, Submit, Watch];
+        if self.is_admin().await {
+            options.push(Review);
+        }
+
         loop {
-            match options[self.single_select(options).await?] {
+            let option = options[self.single_select(options.as_slice()).await?].clone();
+            info!(?option, "menu option picked");
+
+            match option {
                 Info => {
                     // TODO: formatting and copy pass
                     self.print(Self::fixed_width("Hey, I'm Cheru! I'm a 17 y/o Hack Clubber working @ Hack Club HQ in Vermont. This month, I'm running Cargo Cult: a program to help you write your first Rust app! (Join us in #rust on the Hack Club Slack!) \r\n\r\n\
@@ -178,7 +370,9 @@ impl<Out: Write+Send, F> App<Out, F> where F: FnOnce() {
                     )?;
                 },
                 Gallery => return self.gallery().await,
-                Submit => return self.submission_form().await
+                Submit => return self.submission_form().await,
+                Watch => self.watch().await?,
+                Review => return self.review().await
             }
 
             self.newline()?;
@@ -187,97 +381,498 @@ impl<Out: Write+Send, F> App<Out, F> where F: FnOnce() {
 
     pub async fn gallery(&mut self) -> std::io::Result<()> {
         // TODO: error handling?
-        let responses = SubmissionsAirtableBase::new().get().await.expect("getting submissions to wrok");
+        let responses = self.store.gallery().await.expect("getting submissions to wrok");
+        METRICS.gallery_fetches_total.inc();
 
         let width =  min(self.params.clone().lock().await.col_width as usize, 100);
 
-        let result = self.single_select(
-            responses.iter().map(
-                |resp| Self::fixed_width(format!("{}\r\n{}", resp.package_name.clone().unwrap(), resp.description), width)
-            ).collect::<Vec<String>>().as_slice()
-        ).await?;
-        let result = responses.get(result).expect("result value to exist");
+        loop {
+            let result = self.single_select(
+                responses.iter().map(
+                    |resp| Self::fixed_width(format!("{}\r\n{}", resp.package_name.clone().unwrap(), resp.description.replace('\n', "\r\n")), width)
+                ).collect::<Vec<String>>().as_slice()
+            ).await?;
+            let result = responses.get(result).expect("result value to exist");
+
+            let cmd_name = result.package_name.clone().unwrap();
+            let cmd_name = cmd_name.as_str();
+            let project_name = result.name.as_str();
+            info!(crate_name = cmd_name, "gallery project picked");
+
+            if self.docker_session(cmd_name, project_name).await {
+                return Ok(());
+            }
+            // they left the queue (or couldn't get a sandbox), so let them pick again
+            self.newline()?;
+        }
+    }
+    
+    async fn try_project(&mut self, name: &str) -> std::io::Result<()> {
+        if let Some(project) = self.find_project(name).await? {
+            self.docker_session(&project.crate_name(), &project.name).await;
+        }
 
-        let cmd_name = result.package_name.clone().unwrap();
-        let cmd_name = cmd_name.as_str();
-        let project_name = result.name.as_str();
+        Ok(())
+    }
 
-        self.docker_session(cmd_name, project_name).await;
+    async fn readme(&mut self, name: &str) -> std::io::Result<()> {
+        if let Some(project) = self.find_project(name).await? {
+            let crate_name = project.crate_name();
+            self.sandbox(&crate_name, &["readme", &crate_name]).await;
+        }
 
         Ok(())
     }
-    
-    pub async fn run_project(&mut self, name: String) -> std::io::Result<()> {
-        let responses = SubmissionsAirtableBase::new().get().await.expect("getting submissions to wrok");
-        let Some(result) = responses.iter().find(|x| x.package_name.clone().unwrap() == name) else {
-            self.println("The project could not be found: ".to_owned() + &*name)?;
-            self.exit().await;
-        };
 
-        self.docker_session(&result.package_name.clone().unwrap(), &result.name).await; 
-        
-        self.exit().await;
+    /// Looks up a project in the gallery, telling the user if it isn't there.
+    async fn find_project(&mut self, name: &str) -> std::io::Result<Option<FormData>> {
+        let responses = self.store.gallery().await.expect("getting submissions to work");
+        METRICS.gallery_fetches_total.inc();
+
+        let project = responses.into_iter().find(|project| project.crate_name() == name);
+        if project.is_none() {
+            self.println(format!("The project could not be found: {name}"))?;
+        }
+
+        Ok(project)
     }
 
-    async fn docker_session(&mut self, cmd_name: &str, author_name: &str) {
-        let mut session = SSHForwardingSession::connect(
-            "id_ed25519",
-            "cargo-cult",
-            "localhost:2222",
+    async fn docker_session(&mut self, cmd_name: &str, author_name: &str) -> bool {
+        let username = self.params.lock().await.username.clone();
+
+        self.sandbox(cmd_name, &["ssh-entrypoint", &username, cmd_name, author_name]).await
+    }
+
+    /// Runs `cargo-cult <args>` in a fresh sandbox container, with the user's terminal attached.
+    /// `label` names the session's recording. Returns false if the user never got a sandbox,
+    /// because they hit a limit, gave up waiting for one or it couldn't be started.
+    async fn sandbox(&mut self, label: &str, args: &[&str]) -> bool {
+        let Ok(Some(_permit)) = self.wait_for_sandbox(label).await else {
+            return false;
+        };
+        let params = self.params.lock().await.clone();
+
+        // the sandboxed shell turns bracketed paste on itself if it wants it
+        let _ = self.out.execute(DisableBracketedPaste);
+
+        let live = LiveSession::start(&params.username);
+        let span = info_span!("sandbox", label, watch_code = %live.watch_code);
+        let started = Instant::now();
+        info!(parent: &span, ?args, "sandbox started");
+        let _running = METRICS.sandbox_sessions.track();
+        // the invite code lets people type into the session, so it's shown here once and kept
+        // out of the recording
+        self.out.pause_recording().await;
+        let _ = self.println(format!("  Others can watch this session from the menu with the code {}, or join in with the code {}",
+                                     live.watch_code.clone().bold(), live.invite_code.clone().bold()));
+        self.out.resume_recording().await;
+
+        let connected = SSHForwardingSession::connect(
+            SANDBOX_KEY,
+            SANDBOX_USER,
+            SANDBOX_HOST,
             self.params.clone(),
             &mut self.input,
             &mut self.out
-        ).await.unwrap();
+        ).await.map_err(|e| e.to_string());
+        let mut session = match connected {
+            Ok(session) => session,
+            Err(e) => {
+                warn!(parent: &span, error = %e, "couldn't connect to the sandbox host");
+                let _ = self.println("  Sorry, the sandbox couldn't be started. Please try again in a bit.".white().on_dark_red());
+                let _ = self.out.execute(EnableBracketedPaste);
+                return false;
+            }
+        };
+
+        match Recording::start(label, &params) {
+            Ok(Some(recording)) => session.record(recording),
+            Ok(None) => {}
+            Err(e) => warn!(error = %e, "couldn't start recording")
+        }
+        session.share(Arc::clone(&live));
+
+        let args = args.iter().map(|arg| shell_quote(arg)).collect::<Vec<String>>().join(" ");
+
+        let result = timeout(Duration::from_secs(60 * 30),
+                             session.call(format!("docker run -it --entrypoint cargo-cult cargo-cult {}", args).as_str())
+        ).instrument(span.clone()).await;
+        drop(session);
+
+        match result {
+            Ok(Ok(exit_status)) => info!(parent: &span, exit_status, seconds = started.elapsed().as_secs(), "sandbox stopped"),
+            Ok(Err(e)) => warn!(parent: &span, error = %e, seconds = started.elapsed().as_secs(), "sandbox failed"),
+            Err(_) => info!(parent: &span, seconds = started.elapsed().as_secs(), "sandbox timed out")
+        }
+        METRICS.sandbox_session_duration.observe(started.elapsed());
+        drop(live);
+
+        let _ = self.out.execute(SetTitle("cargo cult"));
+        let _ = self.out.execute(EnableBracketedPaste);
+
+        true
+    }
+
+    /// Queues for a sandbox slot, showing the user their place in line until it's their turn.
+    /// Returns `None` if they're refused or leave the queue.
+    async fn wait_for_sandbox(&mut self, label: &str) -> std::io::Result<Option<Permit>> {
+        let params = self.params.lock().await.clone();
+
+        let ticket = match LIMITS.queue_sandbox(params.peer_ip, &params.rate_limit_key()) {
+            Ok(ticket) => ticket,
+            Err(limit) => {
+                info!(label, reason = limit.reason, "sandbox refused");
+                self.show_limit(&limit)?;
+                return Ok(None);
+            }
+        };
+
+        let mut queued = false;
+
+        loop {
+            let changed = LIMITS.sandbox_changed();
+
+            match LIMITS.try_start_sandbox(&ticket) {
+                SandboxSlot::Ready(permit) => {
+                    if queued { self.newline()?; }
+                    return Ok(Some(permit));
+                }
+                SandboxSlot::Refused(limit) => {
+                    info!(label, reason = limit.reason, "sandbox refused");
+                    if queued { self.newline()?; }
+                    self.show_limit(&limit)?;
+                    return Ok(None);
+                }
+                SandboxSlot::Waiting { position, estimated_wait } => {
+                    if !queued {
+                        info!(label, position, "waiting for a sandbox");
+                        queued = true;
+                    }
+
+                    execute!(
+                        self.out,
+                        MoveToColumn(0),
+                        Clear(CurrentLine),
+                        Print(format!("  You're #{} in line for a sandbox (about {}). Press Esc to go back.", position, minutes(estimated_wait)).bold())
+                    )?;
+                }
+            }
+
+            tokio::select! {
+                _ = changed => {}
+                code = self.input.recv() => match code.map(|code| code.ascii_code) {
+                    Some(Some(Esc | Char('q'))) => {
+                        info!(label, "left the sandbox queue");
+                        self.newline()?;
+                        return Ok(None);
+                    }
+                    Some(Some(EoT)) | None => self.exit().await,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    async fn watch(&mut self) -> std::io::Result<()> {
+        self.println("  Enter the code shown to the person whose session you want to see:".bold())?;
+        let code = self.prompt("ABC123", true).await?;
+
+        self.watch_code(&code).await
+    }
+
+    /// Follows someone else's sandbox session until it ends or the user leaves. With the watch code
+    /// it's read-only; with the invite code the user joins in and can type into it too.
+    async fn watch_code(&mut self, code: &str) -> std::io::Result<()> {
+        let params = self.params.lock().await.clone();
+        let (live, guest) = match (LiveSession::find(code), LiveSession::find_invite(code)) {
+            (Some(live), _) => (live, None),
+            (None, Some(live)) => {
+                let guest = live.join(&params.username, params.col_width, params.row_height);
+                (live, Some(guest))
+            }
+            (None, None) => {
+                self.println("  There's no live session with that code.")?;
+                return Ok(());
+            }
+        };
 
+        info!(host = live.host, watch_code = live.watch_code, joined = guest.is_some(), "watching a live session");
+
+        if guest.is_some() {
+            // the shared shell turns bracketed paste on itself if it wants it
+            self.out.execute(DisableBracketedPaste)?;
+            self.println(format!("  Joined {}'s session. Press Ctrl-] to leave.", live.host))?;
+        } else {
+            self.println(format!("  Watching {}'s session. Press q to stop watching.", live.host))?;
+        }
+
+        let (scrollback, mut events) = live.watch();
+        self.out.write_all(&scrollback)?;
+        self.flush()?;
+
+        let message = loop {
+            tokio::select! {
+                event = events.recv() => match event {
+                    Ok(LiveEvent::Output(data)) => {
+                        self.out.write_all(&data)?;
+                        self.flush()?;
+                        self.out.ready().await?;
+                    }
+                    // a slow viewer misses some output rather than holding up the session
+                    Err(RecvError::Lagged(_)) => {}
+                    Ok(LiveEvent::Ended) | Err(RecvError::Closed) => break "The session has ended.",
+                },
+                code = self.input.recv() => {
+                    let Some(code) = code else { self.exit().await };
+
+                    match (&guest, code.ascii_code) {
+                        (Some(_), Some(Ctrl(']'))) => break "Left the session.",
+                        (Some(guest), _) => guest.send(&code.raw_bytes),
+                        (None, Some(Char('q') | Esc)) => break "Stopped watching.",
+                        (None, Some(EoT)) => self.exit().await,
+                        _ => {}
+                    }
+                }
+            }
+        };
+        drop(guest);
+        info!(watch_code = live.watch_code, message, "stopped watching a live session");
+
+        // undo whatever the stream left behind: the shared session's status line, styling, and a
+        // full-screen program's alternate screen, hidden cursor, mouse tracking and cursor keys
+        self.println(format!("\x1b[r\x1b[0m\x1b[?1049l\x1b[?25h\x1b[?1000l\x1b[?1l\x1b[{};1H\r\n  {}", params.row_height, message))?;
+        self.out.execute(EnableBracketedPaste)?;
+
+        Ok(())
+    }
+
+    async fn is_admin(&self) -> bool {
+        let Some(key_fingerprint) = self.params.lock().await.key_fingerprint.clone() else { return false };
+
+        env::var("ADMIN_KEYS").unwrap_or_default()
+            .split(',')
+            .any(|admin_key| admin_key.trim() == key_fingerprint)
+    }
+
+    /// Lets an admin go through pending submissions, try them out and approve or reject them.
+    async fn review(&mut self) -> std::io::Result<()> {
+        if !self.is_admin().await {
+            return Ok(());
+        }
+
+        let width = min(self.params.clone().lock().await.col_width as usize, 100);
+
+        loop {
+            let pending = self.store.pending().await.expect("getting pending submissions to work");
+            if pending.is_empty() {
+                self.println("  Nothing left to review!".bold())?;
+                return Ok(());
+            }
+
+            self.println(format!("  {} submissions waiting for review:", pending.len()).bold())?;
+            let mut options: Vec<String> = pending.iter().map(|record| {
+                let fields = &record.fields;
+                Self::fixed_width(format!("{} ({}, {})\r\n{}", fields.crate_name(), fields.submission_type, fields.slack_handle, fields.description.replace('\n', "\r\n")), width)
+            }).collect();
+            options.push("Done".to_string());
+
+            let Some(record) = pending.into_iter().nth(self.single_select(options.as_slice()).await?) else {
+                return Ok(());
+            };
+
+            self.review_submission(record, width).await?;
+        }
+    }
+
+    async fn review_submission(&mut self, record: Record<FormData>, width: usize) -> std::io::Result<()> {
+        let fields = &record.fields;
+        let crate_name = fields.crate_name();
         let username = self.params.lock().await.username.clone();
 
-        let _ = timeout(Duration::from_secs(60 * 30),
-                        session.call(format!("docker run -it cargo-cult '{}' '{}' '{}'", username, cmd_name, author_name).as_str())
-        ).await;
+        self.println(Self::text_box(crate_name.as_str().white().bold(), Color::DarkBlue, 0, 1, 2))?;
+        self.print(Self::fixed_width(format!(
+            "{} by {} ({})\r\n{}\r\nHours: {}\r\n\r\n{}",
+            fields.submission_type, fields.name, fields.slack_handle, fields.package_link, fields.hours,
+            fields.description.replace('\n', "\r\n")
+        ), width))?;
+        self.newline()?;
+
+        let options = &["Read the README", "Try it in a sandbox", "Run the criteria check", "Approve", "Reject", "Back"];
+        loop {
+            match self.single_select(options).await? {
+                0 => { self.sandbox(&crate_name, &["review-entrypoint", &username, &crate_name, "--readme"]).await; }
+                1 => { self.sandbox(&crate_name, &["review-entrypoint", &username, &crate_name]).await; }
+                2 => self.check_submission(&record).await?,
+                choice @ (3 | 4) => {
+                    let status = if choice == 3 { "Approved" } else { "Rejected" };
+
+                    self.println(format!("  Any notes for {}? ({})", fields.name, status.to_lowercase()).bold())?;
+                    let note = self.prompt("Looks great!", false).await?;
+                    self.newline()?;
+
+                    self.store.set_review(&record.id, status, &note).await.expect("saving the review to work");
+                    info!(crate_name, record_id = record.id, status, "submission reviewed");
+
+                    self.println(format!("   {} {}. ", status, crate_name).white().bold().on_dark_blue())?;
+                    self.newline()?;
+                    return Ok(());
+                }
+                _ => return Ok(())
+            }
+
+            self.newline()?;
+        }
+    }
+
+    /// Runs the criteria check in a throwaway sandbox and attaches the report to the submission.
+    /// It's attached from here, so the sandbox (which runs the crate's build scripts) never needs
+    /// the Airtable key.
+    async fn check_submission(&mut self, record: &Record<FormData>) -> std::io::Result<()> {
+        let crate_name = record.fields.crate_name();
+        let Some(_permit) = self.wait_for_sandbox(&crate_name).await? else {
+            return Ok(());
+        };
+
+        self.println("  Running the criteria check. Installing the crate can take a few minutes...".bold())?;
+        let command = format!("docker run --rm --entrypoint cargo-cult cargo-cult check {}", shell_quote(&crate_name));
+        let report = match timeout(CHECK_TIMEOUT, ssh_client::output(SANDBOX_KEY, SANDBOX_USER, SANDBOX_HOST, &command)).await {
+            // check exits with 1 when the crate fails it, which is still a report
+            Ok(Ok((0 | 1, stdout))) if !stdout.is_empty() => Ok(String::from_utf8_lossy(&stdout).into_owned()),
+            Ok(Ok((status, _))) => Err(format!("the sandbox exited with {status}")),
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(_) => Err(format!("it didn't finish within {}", minutes(CHECK_TIMEOUT)))
+        };
+        let report = match report {
+            Ok(report) => report,
+            Err(e) => {
+                warn!(crate_name, error = e, "couldn't run the criteria check");
+                return self.println(format!("  Sorry, the check couldn't be run: {e}").white().on_dark_red());
+            }
+        };
+
+        // it quotes the crate's build output, which doesn't get to move the cursor around
+        let report: String = report.chars().filter(|&c| c == '\n' || !c.is_control()).collect();
+        self.print(report.replace('\n', "\r\n"))?;
+
+        match self.store.set_check_report(&record.id, &report).await {
+            Ok(()) => {
+                info!(crate_name, record_id = record.id, "check report attached");
+                self.println("  Attached the report to the submission.".bold())
+            }
+            Err(e) => {
+                warn!(crate_name, record_id = record.id, error = %e, "couldn't attach the check report");
+                self.println(format!("  Sorry, the report couldn't be attached: {e}").white().on_dark_red())
+            }
+        }
     }
 
+    /// The submission form (or the update form, for updates). Emails and addresses typed into
+    /// it stay out of the session's recording, which isn't encrypted like the local store is.
     async fn submission_form(&mut self) -> std::io::Result<()> {
+        self.out.pause_recording().await;
+        let result = self.fill_in_submission().await;
+        self.out.resume_recording().await;
+
+        result
+    }
+
+    async fn fill_in_submission(&mut self) -> std::io::Result<()> {
         let mut data = FormData::new();
 
-        self.println("Are you submitting a new project or an update?".bold())?;
-        let options = &["Submission", "Update"];
-        data.submission_type = options[self.single_select(options).await?].into();
+        let draft = self.draft().await;
+        let resumed = match draft {
+            Some(draft) => {
+                self.println("You didn't finish your last submission. Want to pick up where you left off?".bold())?;
+                let options = &["Resume your draft", "Start over"];
+                let resume = self.single_select(options).await? == 0;
+                if resume {
+                    data = draft;
+                }
+                resume
+            }
+            None => false
+        };
+
+        if !resumed {
+            self.println("Are you submitting a new project or an update?".bold())?;
+            let options = &["Submission", "Update"];
+            data.submission_type = options[self.single_select(options).await?].into();
+        }
+
+        // only taken once they've answered something, so someone who opened the form and walked
+        // away doesn't hold up a restart
+        let _submitting = shutdown::submitting();
+
+        if !resumed {
+            if data.submission_type == "Update" {
+                match self.find_previous_submission().await? {
+                    Some(original) => return self.update_form(original).await,
+                    None => {
+                        self.println("  No problem, let's start from the top.")?;
+                        self.newline()?;
+                    }
+                }
+            }
+
+            // returning users (recognized by their SSH key) get their details filled in
+            if let Some(identity) = self.identity().await {
+                data.name = identity.name;
+                data.slack_handle = identity.slack_handle;
+                data.email = identity.email;
+            }
+        }
 
         self.println("  First thing's first... what's your name?".bold())?;
-        data.name = self.prompt("Fiona Hackworth", true).await?;
+        self.ask(&mut data, |data| &mut data.name, "Fiona Hackworth", true).await?;
         self.newline()?;
 
         self.println(format!("  Hi, {}! What's your Slack handle?", data.name).bold())?;
-        data.slack_handle = self.prompt("@fiona", true).await?;
+        self.ask(&mut data, |data| &mut data.slack_handle, "@fiona", true).await?;
         self.newline()?;
 
         self.println("  Now, what's your email?".bold())?;
-        data.email = self.prompt("fiona@hackclub.com", true).await?;
+        self.ask(&mut data, |data| &mut data.email, "fiona@hackclub.com", true).await?;
         self.newline()?;
 
         self.println("  Now, for your address. Please fill in the following:".bold())?;
-        data.address_line1 = self.prompt("Address Line 1", true).await?;
-        data.address_line2 = self.prompt("Address Line 2 (optional)", false).await?;
-        data.city = self.prompt("City", true).await?;
-        data.state = self.prompt("State/Province", true).await?;
-        data.zip = self.prompt("ZIP/Postal Code", true).await?;
-        data.country = self.prompt("Country", true).await?;
+        self.ask(&mut data, |data| &mut data.address_line1, "Address Line 1", true).await?;
+        self.ask(&mut data, |data| &mut data.address_line2, "Address Line 2 (optional)", false).await?;
+        self.ask(&mut data, |data| &mut data.city, "City", true).await?;
+        self.ask(&mut data, |data| &mut data.state, "State/Province", true).await?;
+        self.ask(&mut data, |data| &mut data.zip, "ZIP/Postal Code", true).await?;
+        self.ask(&mut data, |data| &mut data.country, "Country", true).await?;
         self.newline()?;
 
         self.println(format!("  What's the link to your package on {}?", "crates.io".white().on_dark_magenta()).bold())?;
-        data.package_link = self.prompt("https://crates.io/crates/hc-cargo-cult", true).await?;
+        self.ask(&mut data, |data| &mut data.package_link, "https://crates.io/crates/hc-cargo-cult", true).await?;
         self.newline()?;
 
         self.println("  Write a short description for your project.".bold())?;
-        data.description = self.prompt("A CLI form to collect responses for the Cargo Cult YSWS.", true).await?;
+        data.description = self.text_area("A CLI form to collect responses for the Cargo Cult YSWS.", &data.description.clone(), true).await?;
+        self.save_draft(&data).await;
         self.newline()?;
 
         self.println("  How many hours did you spend on your project?".bold())?;
-        data.hours = self.prompt("3 hours, plus 5 hours learning Rust", true).await?;
+        self.ask(&mut data, |data| &mut data.hours, "3 hours, plus 5 hours learning Rust", true).await?;
         self.newline()?;
 
-        let mut airtable = SubmissionsAirtableBase::new();
-        airtable.create(data).await.expect("uploading to airtable to work");
+        self.remember_identity(&data).await;
+
+        let rate_limit_key = self.params.lock().await.rate_limit_key();
+        if let Err(limit) = LIMITS.submit(&rate_limit_key) {
+            self.show_limit(&limit)?;
+            if self.draft_owner().await.is_some() {
+                self.println("  Your answers are saved as a draft, so you can pick up where you left off.")?;
+            }
+            return Ok(());
+        }
+
+        let crate_name = data.crate_name();
+        self.store.create(data).await.expect("uploading to airtable to work");
+        info!(crate_name, "submission created");
+        METRICS.submissions_total.inc();
+
+        self.discard_draft().await;
 
         self.println("   Wahoo! Thanks for submitting. ".white().bold().on_dark_blue())?;
         self.newline()?;
@@ -285,7 +880,151 @@ impl<Out: Write+Send, F> App<Out, F> where F: FnOnce() {
         Ok(())
     }
 
+    /// Prompts for one field of the form, starting from its current value, and saves the draft.
+    async fn ask(&mut self, data: &mut FormData, field: fn(&mut FormData) -> &mut String, default_text: &str, required: bool) -> std::io::Result<()> {
+        let initial = field(data).clone();
+        *field(data) = self.prompt_with(default_text, &initial, required).await?;
+        self.save_draft(data).await;
+
+        Ok(())
+    }
+
+    /// Lets the user pick one of their earlier projects. They're found from the details saved for
+    /// the user's SSH key, never from a handle or email typed in: an update carries over the
+    /// original's email and address, and anyone can type someone else's.
+    async fn find_previous_submission(&mut self) -> std::io::Result<Option<Record<FormData>>> {
+        let Some(identity) = self.identity().await else {
+            self.println("  We can only take updates from the SSH key you submitted with.".bold())?;
+            return Ok(None);
+        };
+
+        // the Slack handle has to match as well, since the email is what's looked up
+        let handle = |handle: &str| handle.trim().trim_start_matches('@').to_lowercase();
+        let previous: Vec<Record<FormData>> = self.store.find_by_submitter(&identity.email).await.expect("looking up submissions to work")
+            .into_iter()
+            .filter(|record| handle(&record.fields.slack_handle) == handle(&identity.slack_handle))
+            .collect();
+        if previous.is_empty() {
+            self.println("  We couldn't find any projects submitted with your details.".bold())?;
+            return Ok(None);
+        }
+
+        let width = min(self.params.clone().lock().await.col_width as usize, 100);
+
+        self.println("  Which project are you updating?".bold())?;
+        let mut options: Vec<String> = previous.iter().map(|record| {
+            let fields = &record.fields;
+            Self::fixed_width(format!("{}\r\n{}", fields.crate_name(), fields.description.replace('\n', "\r\n")), width)
+        }).collect();
+        options.push("It's not listed".to_string());
+
+        let index = self.single_select(options.as_slice()).await?;
+
+        Ok(previous.into_iter().nth(index))
+    }
+
+    /// The rest of the form for an update: everything we already know from the original
+    /// submission is carried over, so only the new details are asked for.
+    async fn update_form(&mut self, original: Record<FormData>) -> std::io::Result<()> {
+        let mut data = original.fields;
+        let name = data.crate_name();
+
+        self.println(format!("  Welcome back, {}! What's new in {}?", data.name, name).bold())?;
+        data.description = self.text_area("Added a --verbose flag and colored output.", "", true).await?;
+        self.newline()?;
+
+        self.println("  How many hours did you spend on this update?".bold())?;
+        data.hours = self.prompt("4 hours", true).await?;
+        self.newline()?;
+
+        self.remember_identity(&data).await;
+
+        let rate_limit_key = self.params.lock().await.rate_limit_key();
+        if let Err(limit) = LIMITS.submit(&rate_limit_key) {
+            return self.show_limit(&limit);
+        }
+
+        self.store.update(&original.id, data).await.expect("uploading to airtable to work");
+        info!(crate_name = name, original_id = original.id, "update submitted");
+        METRICS.submissions_total.inc();
+
+        self.println("   Wahoo! Thanks for the update. ".white().bold().on_dark_blue())?;
+        self.newline()?;
+
+        Ok(())
+    }
+
+    /// The contact details saved for the user's SSH key, if they've submitted before.
+    async fn identity(&self) -> Option<Identity> {
+        let key_fingerprint = self.params.lock().await.key_fingerprint.clone()?;
+
+        match self.local.identity(&key_fingerprint).await {
+            Ok(identity) => identity,
+            Err(e) => {
+                warn!(error = %e, "couldn't read identity");
+                None
+            }
+        }
+    }
+
+    async fn remember_identity(&self, data: &FormData) {
+        let Some(key_fingerprint) = self.params.lock().await.key_fingerprint.clone() else { return };
+
+        let identity = Identity {
+            name: data.name.clone(),
+            slack_handle: data.slack_handle.clone(),
+            email: data.email.clone()
+        };
+
+        if let Err(e) = self.local.save_identity(&key_fingerprint, identity).await {
+            warn!(error = %e, "couldn't save identity");
+        }
+    }
+
+    /// Drafts are kept per SSH key. Usernames are whatever the client says they are, so people
+    /// connecting without a key don't get drafts at all, rather than anyone who guesses their
+    /// username getting their email and address.
+    async fn draft_owner(&self) -> Option<String> {
+        self.params.lock().await.key_fingerprint.clone()
+    }
+
+    async fn draft(&self) -> Option<FormData> {
+        let owner = self.draft_owner().await?;
+
+        match self.local.draft(&owner).await {
+            Ok(draft) => draft,
+            Err(e) => {
+                warn!(error = %e, "couldn't read draft");
+                None
+            }
+        }
+    }
+
+    async fn save_draft(&self, data: &FormData) {
+        let Some(owner) = self.draft_owner().await else { return };
+
+        if let Err(e) = self.local.save_draft(&owner, data).await {
+            warn!(error = %e, "couldn't save draft");
+        }
+    }
+
+    async fn discard_draft(&self) {
+        let Some(owner) = self.draft_owner().await else { return };
+
+        if let Err(e) = self.local.delete_draft(&owner).await {
+            warn!(error = %e, "couldn't delete draft");
+        }
+    }
+
     async fn prompt(&mut self, default_text: &str, required: bool) -> std::io::Result<String> {
+        self.prompt_with(default_text, "", required).await
+    }
+
+    /// Like [`Self::prompt`], but starts out with `initial` already typed in.
+    async fn prompt_with(&mut self, default_text: &str, initial: &str, required: bool) -> std::io::Result<String> {
+        // leave room for the "> " and the cursor
+        let visible_width = (self.params.lock().await.col_width as usize).saturating_sub(3);
+
         let mut render = |text: String| -> Result<(), std::io::Error> {
             execute!(
             self.out,
@@ -298,14 +1037,14 @@ impl<Out: Write+Send, F> App<Out, F> where F: FnOnce() {
             Ok(())
         };
 
-        let mut input = "".to_string();
+        let mut input = initial.to_string();
         let mut first_pass = true;
 
         while first_pass || (required && input.is_empty()) {
             if !first_pass && input.is_empty() {
                 render("This field is required!".white().on_dark_red().slow_blink().to_string())?;
             } else {
-                render(input.clone())?;
+                render(Self::tail_to_width(&input, visible_width).to_string())?;
             }
 
             first_pass = false;
@@ -313,19 +1052,23 @@ impl<Out: Write+Send, F> App<Out, F> where F: FnOnce() {
             while let Some(terminal_code) = self.input.recv().await {
                 if let Some(code) = terminal_code.ascii_code {
                     match code {
-                        Backspace => { input.pop(); }
-                        Enter => break,
-                        Char(c) => {
-                            if let Ok(text) = str::from_utf8(&[c]) {
-                                input.push_str(text);
+                        Backspace if terminal_code.modifiers.alt => Self::delete_word(&mut input),
+                        Ctrl('w') => Self::delete_word(&mut input),
+                        Ctrl('u') => input.clear(),
+                        Backspace => {
+                            if let Some((index, _)) = input.grapheme_indices(true).next_back() {
+                                input.truncate(index);
                             }
                         }
+                        Enter => break,
+                        Char(c) => input.push(c),
+                        Paste(text) => input.push_str(&Self::single_line(&text)),
                         EoT => self.exit().await,
                         _ => {}
                     }
                 }
 
-                render(input.clone())?;
+                render(Self::tail_to_width(&input, visible_width).to_string())?;
             }
         }
 
@@ -334,6 +1077,160 @@ impl<Out: Write+Send, F> App<Out, F> where F: FnOnce() {
         Ok(input)
     }
 
+    /// Multi-line version of [`Self::prompt_with`] for long-form answers. Text is wrapped to the
+    /// terminal width, Enter inserts a newline and Ctrl-D finishes.
+    async fn text_area(&mut self, default_text: &str, initial: &str, required: bool) -> std::io::Result<String> {
+        // leave room for the "> " and the cursor
+        let width = (self.params.lock().await.col_width as usize).saturating_sub(3);
+
+        let mut rendered_row = 0;
+
+        let mut render = |text: &str, cursor: usize, placeholder: Option<String>, done: bool| -> std::io::Result<()> {
+            let (text, cursor) = if placeholder.is_some() { ("", 0) } else { (text, cursor) };
+            let rows = Self::layout_rows(text, width);
+            let (cursor_row, cursor_col) = Self::cursor_position(text, &rows, cursor);
+
+            if rendered_row > 0 {
+                self.out.queue(MoveUp(rendered_row as u16))?;
+            }
+            queue!(self.out, MoveToColumn(0), Clear(FromCursorDown))?;
+
+            for (i, &(start, end)) in rows.iter().enumerate() {
+                if i > 0 {
+                    self.out.queue(Print("\r\n"))?;
+                }
+                self.out.queue(Print(if i == 0 { "> ".reset().bold() } else { "  ".reset() }))?;
+                match placeholder {
+                    Some(ref placeholder) => self.out.queue(Print(placeholder))?,
+                    None => self.out.queue(Print(&text[start..end]))?
+                };
+            }
+
+            if !done {
+                // cut to fit, since a wrapped hint would throw off moving back up to the cursor
+                let hint: String = "Enter: new line, Ctrl-D: done".chars().take(width).collect();
+                self.out.queue(Print(format!("\r\n  {}", hint.dark_grey())))?;
+                self.out.queue(MoveUp((rows.len() - cursor_row) as u16))?;
+            }
+
+            self.out.queue(MoveToColumn(2 + cursor_col as u16))?;
+            rendered_row = cursor_row;
+
+            self.out.flush()
+        };
+
+        let mut text = initial.to_string();
+        let mut cursor = text.len();
+
+        let placeholder = text.is_empty().then(|| default_text.dark_grey().to_string());
+        render(&text, cursor, placeholder, false)?;
+
+        while let Some(terminal_code) = self.input.recv().await {
+            let mut placeholder = None;
+
+            if let Some(code) = terminal_code.ascii_code {
+                let rows = Self::layout_rows(&text, width);
+                let (row, column) = Self::cursor_position(&text, &rows, cursor);
+
+                match code {
+                    Ctrl('d') if required && text.trim().is_empty() => {
+                        placeholder = Some("This field is required!".white().on_dark_red().slow_blink().to_string());
+                    }
+                    Ctrl('d') => break,
+                    Char(c) => {
+                        text.insert(cursor, c);
+                        cursor += c.len_utf8();
+                    }
+                    Enter => {
+                        text.insert(cursor, '\n');
+                        cursor += 1;
+                    }
+                    Paste(pasted) => {
+                        let pasted = Self::multi_line(&pasted);
+                        text.insert_str(cursor, &pasted);
+                        cursor += pasted.len();
+                    }
+                    Backspace => {
+                        if let Some((index, _)) = text[..cursor].grapheme_indices(true).next_back() {
+                            text.replace_range(index..cursor, "");
+                            cursor = index;
+                        }
+                    }
+                    Delete => {
+                        if let Some(grapheme) = text[cursor..].graphemes(true).next() {
+                            text.replace_range(cursor..cursor + grapheme.len(), "");
+                        }
+                    }
+                    ArrowLeft => {
+                        cursor = text[..cursor].grapheme_indices(true).next_back().map(|(index, _)| index).unwrap_or(0);
+                    }
+                    ArrowRight => {
+                        cursor += text[cursor..].graphemes(true).next().map(str::len).unwrap_or(0);
+                    }
+                    ArrowUp if row > 0 => {
+                        cursor = Self::cursor_at_column(&text, rows[row - 1], column);
+                    }
+                    ArrowDown if row + 1 < rows.len() => {
+                        cursor = Self::cursor_at_column(&text, rows[row + 1], column);
+                    }
+                    Home => {
+                        cursor = text[..cursor].rfind('\n').map(|index| index + 1).unwrap_or(0);
+                    }
+                    End => {
+                        cursor = text[cursor..].find('\n').map(|index| cursor + index).unwrap_or(text.len());
+                    }
+                    EoT => self.exit().await,
+                    _ => {}
+                }
+            }
+
+            if text.is_empty() && placeholder.is_none() {
+                placeholder = Some(default_text.dark_grey().to_string());
+            }
+
+            render(&text, cursor, placeholder, false)?;
+        }
+
+        render(&text, text.len(), None, true)?;
+        self.println("".reset())?;
+
+        Ok(text)
+    }
+
+    /// Wraps each line of `text` to `width`, returning the byte range of every row on screen.
+    fn layout_rows(text: &str, width: usize) -> Vec<(usize, usize)> {
+        let mut rows = Vec::new();
+        let mut offset = 0;
+
+        for line in text.split('\n') {
+            rows.extend(Self::wrap_ranges(line, width).iter().map(|&(start, end)| (offset + start, offset + end)));
+            offset += line.len() + 1;
+        }
+
+        rows
+    }
+
+    /// The (row, column) on screen of the byte offset `cursor` in `text`.
+    fn cursor_position(text: &str, rows: &[(usize, usize)], cursor: usize) -> (usize, usize) {
+        let row = rows.iter().rposition(|&(start, _)| start <= cursor).unwrap_or(0);
+
+        (row, text[rows[row].0..cursor].width())
+    }
+
+    /// The byte offset in `row` closest to (without going past) `column`.
+    fn cursor_at_column(text: &str, (start, end): (usize, usize), column: usize) -> usize {
+        let mut used = 0;
+
+        for (index, grapheme) in text[start..end].grapheme_indices(true) {
+            used += grapheme.width();
+            if used > column {
+                return start + index;
+            }
+        }
+
+        end
+    }
+
     async fn single_select<T: Clone + Display>(&mut self, options: &[T]) -> Result<usize, std::io::Error> {
         let total_lines: usize = {
             let lines = options.iter().map(|option|
@@ -408,8 +1305,8 @@ impl<Out: Write+Send, F> App<Out, F> where F: FnOnce() {
                     ArrowUp => {
                         index = index.saturating_sub(1)
                     }
-                    ArrowDown => {
-                        if index < options.len() - 1 { index += 1 }
+                    ArrowDown if index < options.len() - 1 => {
+                        index += 1
                     }
                     EoT => {
                         self.exit().await;
@@ -428,7 +1325,7 @@ impl<Out: Write+Send, F> App<Out, F> where F: FnOnce() {
 
     fn text_box(text: StyledContent<&str>, bg: Color, padding_y: usize, padding_x: usize, margin_x: usize) -> String {
         let mut result = String::new();
-        let src_len = text.content().len();
+        let src_len = text.content().width();
 
         let margin_x = ||
             " ".repeat(margin_x)
@@ -468,6 +1365,7 @@ impl<Out: Write+Send, F> App<Out, F> where F: FnOnce() {
     async fn slow_print(&mut self, input: String) -> std::io::Result<()> {
         for line in input.split("\r\n") {
             self.println(line)?;
+            self.out.ready().await?;
             tokio::time::sleep(Duration::from_millis(50)).await;
         }
 
@@ -480,21 +1378,765 @@ impl<Out: Write+Send, F> App<Out, F> where F: FnOnce() {
 
     fn fixed_width(input: String, width: usize) -> String {
         input.split("\r\n").map(
-            |line| {
-                let mut result: Vec<String> = vec![String::new()];
-
-                let mut line_num = 0;
+            |line| Self::wrap_ranges(line, width).iter()
+                .map(|&(start, end)| format!("{} \r\n", &line[start..end]))
+                .collect::<String>()
+        ).collect()
+    }
 
-                for word in line.split(' ') {
-                    if result[line_num].len() + word.len() > width {
-                        line_num += 1;
-                        result.push(String::new())
-                    }
-                    result[line_num].push_str(&*(word.to_owned() + " "))
+    /// Word-wraps a single line to `width` columns, returning the byte range of each row.
+    /// Words that don't fit on a row of their own are split.
+    fn wrap_ranges(line: &str, width: usize) -> Vec<(usize, usize)> {
+        let mut rows = vec![(0, 0)];
+        let mut row_width = 0;
+        let mut offset = 0;
+
+        for word in line.split(' ') {
+            for (piece_start, piece) in Self::split_to_width(word, width) {
+                let start = offset + piece_start;
+                let piece_width = piece.width();
+
+                if row_width > 0 && row_width + piece_width > width {
+                    rows.push((start, start));
+                    row_width = 0;
                 }
 
-                result.iter().map(|x| x.to_owned() + "\r\n").collect::<String>()
+                rows.last_mut().unwrap().1 = start + piece.len();
+                row_width += piece_width + 1;
             }
-        ).collect()
+
+            offset += word.len() + 1;
+        }
+
+        rows
+    }
+
+    fn split_to_width(word: &str, width: usize) -> Vec<(usize, &str)> {
+        if width == 0 || word.width() <= width {
+            return vec![(0, word)];
+        }
+
+        let mut pieces = Vec::new();
+        let mut start = 0;
+        let mut used = 0;
+
+        for (index, grapheme) in word.grapheme_indices(true) {
+            // a character wider than the whole row still gets one to itself
+            if used > 0 && used + grapheme.width() > width {
+                pieces.push((start, &word[start..index]));
+                start = index;
+                used = 0;
+            }
+            used += grapheme.width();
+        }
+        pieces.push((start, &word[start..]));
+
+        pieces
+    }
+
+    /// The longest suffix of `text` (on grapheme boundaries) that fits in `width` terminal columns.
+    fn tail_to_width(text: &str, width: usize) -> &str {
+        let mut used = 0;
+        let mut start = text.len();
+
+        for (index, grapheme) in text.grapheme_indices(true).rev() {
+            used += grapheme.width();
+            if used > width { break }
+            start = index;
+        }
+
+        &text[start..]
+    }
+
+    /// Removes the last word (and any whitespace after it), like Ctrl-W in a shell.
+    fn delete_word(input: &mut String) {
+        let start = input.trim_end().trim_end_matches(|c: char| !c.is_whitespace()).len();
+        input.truncate(start);
+    }
+
+    /// Flattens pasted text for a single-line prompt: line breaks and tabs become spaces and
+    /// any trailing newline (e.g. from copying a whole line) is dropped.
+    fn single_line(text: &str) -> String {
+        text.trim_end_matches(['\r', '\n'])
+            .replace("\r\n", " ")
+            .chars()
+            .filter_map(|c| match c {
+                '\r' | '\n' | '\t' => Some(' '),
+                c if c.is_control() => None,
+                c => Some(c)
+            })
+            .collect()
+    }
+
+    /// Normalizes pasted text for a text area: line endings become `\n` and tabs become spaces.
+    fn multi_line(text: &str) -> String {
+        text.replace("\r\n", "\n")
+            .chars()
+            .filter_map(|c| match c {
+                '\r' => Some('\n'),
+                '\t' => Some(' '),
+                c if c.is_control() && c != '\n' => None,
+                c => Some(c)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::style::{Color, Stylize};
+    use crate::database::{FormData, MemoryStore};
+    use crate::harness::{Client, Harness, TestApp};
+    use crate::notices;
+    use std::io::Write;
+    use async_trait::async_trait;
+    use crate::recording::Recording;
+    use crate::route::Route;
+    use crate::screen::Screen;
+    use crate::storage::Identity;
+    use super::{AsyncWriter, OutputSink, MAX_BACKLOG_BYTES};
+
+    const DOWN: &str = "\x1b[B";
+    const UP: &str = "\x1b[A";
+    const ENTER: &str = "\r";
+    const CTRL_D: &str = "\x04";
+
+    /// A client that's stopped reading, or has gone away.
+    struct StuckSink {
+        gone: bool
+    }
+
+    #[async_trait]
+    impl OutputSink for StuckSink {
+        async fn send(&mut self, _data: Vec<u8>) -> std::io::Result<()> {
+            if self.gone {
+                return Err(std::io::Error::other("channel closed"));
+            }
+            std::future::pending().await
+        }
+    }
+
+    fn identity(name: &str, slack_handle: &str, email: &str) -> Identity {
+        Identity { name: name.to_string(), slack_handle: slack_handle.to_string(), email: email.to_string() }
+    }
+
+    fn project(crate_name: &str, author: &str, slack_handle: &str, review_status: &str) -> FormData {
+        FormData {
+            name: author.to_string(),
+            slack_handle: slack_handle.to_string(),
+            email: format!("{}@example.com", author.to_lowercase()),
+            package_link: format!("https://crates.io/crates/{crate_name}"),
+            package_name: Some(crate_name.to_string()),
+            description: format!("{crate_name} does a thing."),
+            hours: "10".to_string(),
+            review_status: review_status.to_string(),
+            ..FormData::new()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn menu_shows_the_options_and_the_info_page() {
+        let mut harness = Harness::start(Route::Menu, MemoryStore::default());
+
+        harness.wait_for("Welcome to the Cargo Cult!").await;
+        {
+            let screen = harness.screen();
+            assert!(screen.contains("> What is this?"));
+            assert!(screen.contains("> See the gallery"));
+            assert!(screen.contains("> Submit your project"));
+            assert!(screen.contains("> Watch or join a live session"));
+            assert!(!screen.contains("Review submissions"));
+            assert_eq!(screen.title(), "cargo cult");
+        }
+
+        harness.keys(ENTER).await;
+        harness.wait_for("Here's the criteria to get a book:").await;
+        // followed by the menu again, where Ctrl-C leaves
+        assert!(harness.screen().contains("> See the gallery"));
+
+        harness.keys("\x03").await;
+        harness.wait_for_exit().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gallery_lists_approved_projects() {
+        let store = MemoryStore::with(vec![
+            project("ferris-says", "Fiona", "@fiona", "Approved"),
+            project("crabby", "Casey", "@casey", "Approved"),
+            project("not-yet", "Nat", "@nat", "")
+        ]).approved_before_review(project("old-favourite", "Olive", "@olive", ""));
+        let mut harness = Harness::start(Route::Menu, store);
+
+        harness.wait_for("See the gallery").await;
+        harness.keys(DOWN).await;
+        harness.keys(ENTER).await;
+
+        harness.wait_for("ferris-says").await;
+        let screen = harness.screen();
+        assert!(screen.contains("> ferris-says"));
+        assert!(screen.contains("ferris-says does a thing."));
+        assert!(screen.contains("> crabby"));
+        assert!(screen.contains("> old-favourite"));
+        assert!(!screen.contains("not-yet"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn admins_approve_and_reject_pending_submissions() {
+        std::env::set_var("ADMIN_KEYS", "SHA256:some-admin, SHA256:test-admin");
+        let store = MemoryStore::with(vec![
+            project("ferris-says", "Fiona", "@fiona", ""),
+            project("crabby", "Casey", "@casey", ""),
+            project("shipped", "Sam", "@sam", "Approved"),
+            project("turned-down", "Nat", "@nat", "Rejected")
+        ]).approved_before_review(project("old-favourite", "Olive", "@olive", ""));
+        let mut harness = Harness::connect(Client::default().with_key("SHA256:test-admin"), Route::Menu, store);
+
+        harness.wait_for("Review submissions (admin)").await;
+        harness.keys(&format!("{DOWN}{DOWN}{DOWN}{DOWN}{ENTER}")).await;
+
+        // approved and rejected ones are done, including those approved before review mode
+        harness.wait_for("2 submissions waiting for review:").await;
+        {
+            let screen = harness.screen();
+            assert!(screen.contains("> ferris-says (Submission, @fiona)"));
+            assert!(screen.contains("> crabby (Submission, @casey)"));
+            assert!(!screen.contains("old-favourite"));
+            assert!(!screen.contains("shipped"));
+            assert!(!screen.contains("turned-down"));
+        }
+
+        harness.keys(ENTER).await;
+        harness.wait_for("Run the criteria check").await;
+        harness.keys(&format!("{DOWN}{DOWN}{DOWN}{ENTER}")).await;
+        harness.wait_for("Any notes for Fiona? (approved)").await;
+        harness.keys("Ship it!\r").await;
+        harness.wait_for("Approved ferris-says.").await;
+
+        harness.wait_for("1 submissions waiting for review:").await;
+        harness.keys(ENTER).await;
+        harness.wait_for("Submission by Casey (@casey)").await;
+        harness.keys(&format!("{DOWN}{DOWN}{DOWN}{DOWN}{ENTER}")).await;
+        harness.wait_for("Any notes for Casey? (rejected)").await;
+        harness.keys("Needs a README\r").await;
+        harness.wait_for("Rejected crabby.").await;
+
+        harness.wait_for("Nothing left to review!").await;
+        harness.wait_for_exit().await;
+
+        let reviews: Vec<(String, String, String)> = harness.store.records().into_iter()
+            .map(|record| (record.fields.crate_name(), record.fields.review_status, record.fields.review_note))
+            .collect();
+        assert_eq!(reviews, [
+            ("ferris-says".to_string(), "Approved".to_string(), "Ship it!".to_string()),
+            ("crabby".to_string(), "Rejected".to_string(), "Needs a README".to_string()),
+            ("shipped".to_string(), "Approved".to_string(), String::new()),
+            ("turned-down".to_string(), "Rejected".to_string(), String::new()),
+            ("old-favourite".to_string(), String::new(), String::new())
+        ]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn submission_form_sends_a_new_submission() {
+        let mut harness = Harness::start(Route::Submit, MemoryStore::default());
+
+        harness.wait_for("Are you submitting a new project or an update?").await;
+        harness.keys(ENTER).await;
+
+        harness.wait_for("what's your name?").await;
+        // empty required fields are refused
+        harness.keys(ENTER).await;
+        harness.wait_for("This field is required!").await;
+        harness.keys("Fiona\r").await;
+
+        harness.wait_for("Hi, Fiona! What's your Slack handle?").await;
+        harness.keys("@fiona\r").await;
+        harness.wait_for("what's your email?").await;
+        harness.keys("fiona@example.com\r").await;
+
+        harness.wait_for("Please fill in the following").await;
+        for answer in ["15 Falls Rd\r", "\r", "Shelburne\r", "VT\r", "05482\r", "USA\r"] {
+            harness.keys(answer).await;
+        }
+
+        harness.wait_for("What's the link to your package").await;
+        harness.keys("https://crates.io/crates/ferris-says\r").await;
+
+        harness.wait_for("Write a short description").await;
+        harness.keys("Says things.\rLike a crab.").await;
+        harness.keys(CTRL_D).await;
+
+        harness.wait_for("How many hours").await;
+        harness.keys("12\r").await;
+
+        harness.wait_for("Wahoo! Thanks for submitting.").await;
+        harness.wait_for_exit().await;
+
+        let records = harness.store.records();
+        assert_eq!(records.len(), 1);
+        let submission = &records[0].fields;
+        assert_eq!(submission.submission_type, "Submission");
+        assert_eq!(submission.name, "Fiona");
+        assert_eq!(submission.slack_handle, "@fiona");
+        assert_eq!(submission.address_line2, "");
+        assert_eq!(submission.country, "USA");
+        assert_eq!(submission.crate_name(), "ferris-says");
+        assert_eq!(submission.description, "Says things.\nLike a crab.");
+        assert_eq!(submission.hours, "12");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn update_form_links_to_the_original_submission() {
+        let store = MemoryStore::with(vec![
+            project("crabby", "Casey", "@casey", "Approved"),
+            project("ferris-says", "Fiona", "@fiona", "Approved")
+        ]);
+        let mut harness = Harness::connect(Client::default().with_key("SHA256:fiona"), Route::Submit, store);
+        harness.local.save_identity("SHA256:fiona", identity("Fiona", "@Fiona", "fiona@example.com")).await.unwrap();
+
+        harness.wait_for("Are you submitting a new project or an update?").await;
+        harness.keys(DOWN).await;
+        harness.keys(ENTER).await;
+
+        harness.wait_for("Which project are you updating?").await;
+        assert!(harness.screen().contains("> ferris-says"));
+        assert!(!harness.screen().contains("crabby"));
+        harness.keys(ENTER).await;
+
+        harness.wait_for("Welcome back, Fiona! What's new in ferris-says?").await;
+        harness.keys("Now in color.").await;
+        harness.keys(CTRL_D).await;
+
+        harness.wait_for("How many hours did you spend on this update?").await;
+        harness.keys("3\r").await;
+
+        harness.wait_for("Wahoo! Thanks for the update.").await;
+        harness.wait_for_exit().await;
+
+        let records = harness.store.records();
+        assert_eq!(records.len(), 3);
+        let update = &records[2].fields;
+        assert_eq!(update.submission_type, "Update");
+        assert_eq!(update.original_submission, vec![records[1].id.clone()]);
+        assert_eq!(update.description, "Now in color.");
+        assert_eq!(update.hours, "3");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn updates_need_a_recognized_key() {
+        let store = MemoryStore::with(vec![project("ferris-says", "Fiona", "@fiona", "Approved")]);
+        let mut harness = Harness::start(Route::Submit, store);
+
+        harness.wait_for("Are you submitting a new project or an update?").await;
+        harness.keys(DOWN).await;
+        harness.keys(ENTER).await;
+
+        harness.wait_for("We can only take updates from the SSH key you submitted with.").await;
+        harness.wait_for("No problem, let's start from the top.").await;
+        assert!(!harness.screen().contains("ferris-says"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn updates_only_offer_projects_matching_the_whole_identity() {
+        let store = MemoryStore::with(vec![project("ferris-says", "Fiona", "@fiona", "Approved")]);
+        // someone who once submitted with Fiona's email, but not as her
+        let mut harness = Harness::connect(Client::default().with_key("SHA256:mallory"), Route::Submit, store);
+        harness.local.save_identity("SHA256:mallory", identity("Mallory", "@mallory", "fiona@example.com")).await.unwrap();
+
+        harness.wait_for("Are you submitting a new project or an update?").await;
+        harness.keys(DOWN).await;
+        harness.keys(ENTER).await;
+
+        harness.wait_for("We couldn't find any projects submitted with your details.").await;
+        assert!(!harness.screen().contains("ferris-says"));
+        assert!(!harness.screen().contains("Fiona"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn recordings_leave_out_the_submission_form() {
+        let dir = std::env::temp_dir().join(format!("cargo-cult-test-recording-{}", std::process::id()));
+        let recording_dir = dir.clone();
+        let mut harness = Harness::run(80, 24, MemoryStore::default(), |mut app| async move {
+            let params = app.params.lock().await.clone();
+            app.out.record(Recording::start_in(recording_dir, "tui", &params).unwrap()).await;
+
+            app.println("Before the form").unwrap();
+            app.submission_form().await.unwrap();
+            app.println("After the form").unwrap();
+            app.out.wait().await;
+        });
+
+        harness.wait_for("Are you submitting a new project or an update?").await;
+        harness.keys(ENTER).await;
+        for answer in ["Fiona\r", "@fiona\r", "fiona@example.com\r", "15 Falls Rd\r", "\r", "Shelburne\r", "VT\r", "05482\r", "USA\r", "https://crates.io/crates/ferris-says\r"] {
+            harness.keys(answer).await;
+        }
+        harness.keys("Says things.").await;
+        harness.keys(CTRL_D).await;
+        harness.keys("12\r").await;
+        harness.wait_for("After the form").await;
+
+        let file = std::fs::read_dir(&dir).unwrap().next().unwrap().unwrap().path();
+        let recording = std::fs::read_to_string(file).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(recording.contains("Before the form"));
+        assert!(recording.contains("After the form"));
+        assert!(!recording.contains("fiona@example.com"));
+        assert!(!recording.contains("Falls Rd"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn drafts_are_offered_again_to_the_same_key() {
+        let client = Client::default().with_key("SHA256:fiona");
+        let mut harness = Harness::connect(client.clone(), Route::Submit, MemoryStore::default());
+
+        harness.wait_for("Are you submitting a new project or an update?").await;
+        harness.keys(ENTER).await;
+        harness.wait_for("what's your name?").await;
+        harness.keys("Fiona\r").await;
+        harness.wait_for("What's your Slack handle?").await;
+
+        let harness = harness.reconnect(client, Route::Submit);
+        harness.wait_for("Want to pick up where you left off?").await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sessions_without_a_key_dont_keep_drafts() {
+        let client = Client::default();
+        let mut harness = Harness::connect(client.clone(), Route::Submit, MemoryStore::default());
+
+        harness.wait_for("Are you submitting a new project or an update?").await;
+        harness.keys(ENTER).await;
+        harness.wait_for("what's your name?").await;
+        harness.keys("Fiona\r").await;
+        harness.wait_for("What's your Slack handle?").await;
+        harness.keys("@fiona\r").await;
+        harness.wait_for("what's your email?").await;
+        harness.keys("fiona@example.com\r").await;
+        harness.wait_for("Please fill in the following").await;
+
+        let (drafts, _) = harness.local.reveal_all().await.unwrap();
+        assert!(drafts.is_empty());
+
+        let harness = harness.reconnect(client, Route::Submit);
+        harness.wait_for("Are you submitting a new project or an update?").await;
+        assert!(!harness.screen().contains("pick up where you left off"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn people_sharing_a_route_username_dont_see_each_others_drafts() {
+        let mut harness = Harness::connect(Client::named("submit"), Route::Submit, MemoryStore::default());
+
+        harness.wait_for("Are you submitting a new project or an update?").await;
+        harness.keys(ENTER).await;
+        harness.wait_for("what's your name?").await;
+        harness.keys("Fiona\r").await;
+        harness.wait_for("What's your Slack handle?").await;
+
+        // someone else, with a key of their own, going to the same route
+        let someone_else = harness.reconnect(Client::named("submit").with_key("SHA256:casey"), Route::Submit);
+        someone_else.wait_for("Are you submitting a new project or an update?").await;
+        assert!(!someone_else.screen().contains("pick up where you left off"));
+
+        let anyone = harness.reconnect(Client::named("submit"), Route::Submit);
+        anyone.wait_for("Are you submitting a new project or an update?").await;
+        assert!(!anyone.screen().contains("pick up where you left off"));
+    }
+
+    #[tokio::test]
+    async fn output_to_a_client_that_stopped_reading_is_capped() {
+        let mut out = AsyncWriter::new(StuckSink { gone: false });
+        let frame = vec![b'x'; 1024];
+
+        let mut written = 0;
+        let error = loop {
+            out.write_all(&frame).unwrap();
+            if let Err(error) = out.flush() {
+                break error;
+            }
+            written += frame.len();
+            assert!(written <= 2 * MAX_BACKLOG_BYTES, "the backlog kept growing");
+        };
+        assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+    }
+
+    #[tokio::test]
+    async fn output_to_a_client_that_went_away_fails() {
+        let mut out = AsyncWriter::new(StuckSink { gone: true });
+
+        out.write_all(b"hello").unwrap();
+        out.flush().unwrap();
+        // the worker stops after the failed send, so later writes have nowhere to go
+        tokio::task::yield_now().await;
+        let later = async {
+            loop {
+                out.write_all(b"again").unwrap();
+                if out.flush().is_err() {
+                    break;
+                }
+                tokio::task::yield_now().await;
+            }
+        };
+        tokio::time::timeout(std::time::Duration::from_secs(5), later).await.expect("writes to start failing");
+    }
+
+    fn options(count: usize) -> Vec<String> {
+        (1..=count).map(|n| format!("option {n}")).collect()
+    }
+
+    /// What `text` looks like printed on a fresh screen.
+    fn printed(cols: usize, rows: usize, text: &str) -> Screen {
+        let mut screen = Screen::new(cols, rows);
+        screen.feed(text.as_bytes());
+        screen
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn single_select_fits_on_screen() {
+        let mut harness = Harness::run(30, 10, MemoryStore::default(), |mut app| async move {
+            app.single_select(&options(4)).await.unwrap();
+        });
+
+        harness.wait_for("option 4").await;
+        harness.screen().assert_snapshot("single_select_fits");
+
+        harness.keys(DOWN).await;
+        harness.screen().assert_snapshot("single_select_fits_second_selected");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn single_select_scrolls_in_a_short_terminal() {
+        let mut harness = Harness::run(30, 6, MemoryStore::default(), |mut app| async move {
+            app.single_select(&options(12)).await.unwrap();
+        });
+
+        harness.wait_for("option 1").await;
+        harness.screen().assert_snapshot("single_select_scroll_start");
+
+        for _ in 0..8 {
+            harness.keys(DOWN).await;
+        }
+        harness.screen().assert_snapshot("single_select_scroll_down");
+
+        // past the last option, the selection stays put
+        for _ in 0..8 {
+            harness.keys(DOWN).await;
+        }
+        harness.screen().assert_snapshot("single_select_scroll_end");
+
+        for _ in 0..9 {
+            harness.keys(UP).await;
+        }
+        harness.screen().assert_snapshot("single_select_scroll_up");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn single_select_scrolls_multi_line_options() {
+        let options: Vec<String> = (1..=5).map(|n| format!("project {n}\r\n  it does thing {n}")).collect();
+        let mut harness = Harness::run(30, 7, MemoryStore::default(), |mut app| async move {
+            app.single_select(&options).await.unwrap();
+        });
+
+        harness.wait_for("project 1").await;
+        harness.screen().assert_snapshot("single_select_multi_line_start");
+
+        for _ in 0..3 {
+            harness.keys(DOWN).await;
+        }
+        harness.screen().assert_snapshot("single_select_multi_line_down");
+    }
+
+    #[test]
+    fn text_box_with_padding_and_margin() {
+        let text_box = TestApp::text_box("Welcome to the Cargo Cult!".white().bold(), Color::DarkRed, 1, 3, 2);
+        printed(40, 5, &text_box).assert_snapshot("text_box_padded");
+    }
+
+    #[test]
+    fn text_box_without_vertical_padding() {
+        let text_box = TestApp::text_box("hc-cargo-cult".white().bold(), Color::DarkBlue, 0, 1, 0);
+        printed(20, 3, &text_box).assert_snapshot("text_box_tight");
+    }
+
+    #[test]
+    fn fixed_width_wraps_to_the_terminal() {
+        let text = "Hey, I'm Cheru! I'm running Cargo Cult: a program to help you write your first Rust app!\r\n\r\nsupercalifragilisticexpialidocious";
+
+        printed(20, 12, &TestApp::fixed_width(text.to_string(), 19)).assert_snapshot("fixed_width_20");
+        printed(40, 8, &TestApp::fixed_width(text.to_string(), 39)).assert_snapshot("fixed_width_40");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn prompt_shows_the_placeholder_then_the_input() {
+        let mut harness = Harness::run(30, 4, MemoryStore::default(), |mut app| async move {
+            app.prompt("your name", false).await.unwrap();
+        });
+
+        harness.wait_for("your name").await;
+        harness.screen().assert_snapshot("prompt_placeholder");
+
+        harness.keys("Fiona").await;
+        harness.screen().assert_snapshot("prompt_typed");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn prompt_keeps_the_end_of_long_input_in_view() {
+        let mut harness = Harness::run(16, 3, MemoryStore::default(), |mut app| async move {
+            app.prompt("a link", false).await.unwrap();
+        });
+
+        harness.wait_for("a link").await;
+        harness.keys("https://crates.io/crates/ferris-says").await;
+        harness.screen().assert_snapshot("prompt_long_input");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn backspace_removes_whole_graphemes() {
+        let mut harness = Harness::run(40, 6, MemoryStore::default(), |mut app| async move {
+            let line = app.prompt("one line", false).await.unwrap();
+            let text = app.text_area("more lines", "", false).await.unwrap();
+            app.println(format!("got {line:?} and {text:?}")).unwrap();
+        });
+
+        // an accented e made of two code points, a family joined by zero-width joiners and a flag
+        harness.wait_for("one line").await;
+        harness.keys("cafe\u{301} 👨\u{200d}👩\u{200d}👧🇯🇵").await;
+        harness.keys("\x7f\x7f\x7f\x7f").await;
+        harness.keys(ENTER).await;
+
+        harness.wait_for("more lines").await;
+        harness.keys("🇯🇵e\u{301}\r👨\u{200d}👩\u{200d}👧").await;
+        harness.keys("\x7f\x7f\x7f").await;
+        harness.keys("\x04").await;
+
+        harness.wait_for("got").await;
+        assert!(harness.screen().contains("got \"caf\" and \"🇯🇵\""));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn notices_show_as_a_banner_without_moving_the_cursor() {
+        let mut harness = Harness::run(40, 5, MemoryStore::default(), |mut app| async move {
+            app.println("  What's your name?").unwrap();
+            app.prompt("your name", false).await.unwrap();
+        });
+
+        harness.wait_for("your name").await;
+        harness.keys("Fi").await;
+        assert_eq!(notices::announce("Restarting in 2 minutes"), 1);
+        harness.wait_for("Restarting in 2 minutes").await;
+        harness.screen().assert_snapshot("notice_banner");
+
+        harness.keys("ona").await;
+        harness.screen().assert_snapshot("notice_banner_typing");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn prompt_refuses_empty_required_input() {
+        let mut harness = Harness::run(40, 3, MemoryStore::default(), |mut app| async move {
+            app.prompt("your email", true).await.unwrap();
+        });
+
+        harness.wait_for("your email").await;
+        harness.keys(ENTER).await;
+        harness.wait_for("This field is required!").await;
+        harness.screen().assert_snapshot("prompt_required");
+    }
+
+    #[test]
+    fn long_words_are_split_to_the_width() {
+        for (word, width, pieces) in [
+            ("crab", 10, vec![(0, "crab")]),
+            ("crab", 0, vec![(0, "crab")]),
+            ("crabs", 2, vec![(0, "cr"), (2, "ab"), (4, "s")]),
+            ("日本語", 4, vec![(0, "日本"), (6, "語")]),
+            ("日本語", 3, vec![(0, "日"), (3, "本"), (6, "語")]),
+            ("日本", 1, vec![(0, "日"), (3, "本")]),
+            ("🦀🦀🦀", 4, vec![(0, "🦀🦀"), (8, "🦀")])
+        ] {
+            assert_eq!(TestApp::split_to_width(word, width), pieces, "{word:?} at {width}");
+        }
+    }
+
+    #[test]
+    fn long_input_keeps_the_tail_that_fits() {
+        for (text, width, tail) in [
+            ("", 5, ""),
+            ("ferris", 10, "ferris"),
+            ("ferris", 6, "ferris"),
+            ("ferris", 3, "ris"),
+            ("ferris", 0, ""),
+            ("日本語", 4, "本語"),
+            // half a wide character doesn't fit, so the column is left empty
+            ("日本語", 3, "語"),
+            ("cafe\u{301}", 2, "fe\u{301}"),
+            ("hi 👨\u{200d}👩\u{200d}👧", 2, "👨\u{200d}👩\u{200d}👧"),
+            ("flags 🇯🇵🇫🇷", 3, "🇫🇷")
+        ] {
+            assert_eq!(TestApp::tail_to_width(text, width), tail, "{text:?} at {width}");
+        }
+    }
+
+    #[test]
+    fn lines_wrap_between_words() {
+        for (line, width, rows) in [
+            ("", 10, vec![""]),
+            ("hello world", 20, vec!["hello world"]),
+            ("hello world", 8, vec!["hello", "world"]),
+            ("hello world", 5, vec!["hello", "world"]),
+            ("supercalifragilistic", 8, vec!["supercal", "ifragili", "stic"]),
+            ("日本語のテキスト", 6, vec!["日本語", "のテキ", "スト"]),
+            ("a 日本語", 5, vec!["a", "日本", "語"]),
+            ("say 🦀 hi", 6, vec!["say 🦀", "hi"])
+        ] {
+            let wrapped: Vec<&str> = TestApp::wrap_ranges(line, width).into_iter().map(|(start, end)| &line[start..end]).collect();
+            assert_eq!(wrapped, rows, "{line:?} at {width}");
+        }
+    }
+
+    #[test]
+    fn cursors_are_placed_by_row_and_column() {
+        let text = "ab\n日本語 x";
+        let rows = TestApp::layout_rows(text, 4);
+        assert_eq!(rows, [(0, 2), (3, 9), (9, 14)]);
+
+        for (cursor, position) in [
+            (0, (0, 0)),
+            (2, (0, 2)),
+            (3, (1, 0)),
+            (6, (1, 2)),
+            (9, (2, 0)),
+            (12, (2, 2)),
+            (14, (2, 4))
+        ] {
+            assert_eq!(TestApp::cursor_position(text, &rows, cursor), position, "cursor at byte {cursor}");
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn text_area_wraps_and_moves_between_lines() {
+        let mut harness = Harness::run(24, 8, MemoryStore::default(), |mut app| async move {
+            let text = app.text_area("what it does", "", false).await.unwrap();
+            app.println(format!("got {text:?}")).unwrap();
+        });
+
+        harness.wait_for("what it does").await;
+        harness.screen().assert_snapshot("text_area_placeholder");
+
+        harness.keys("Says hi in 日本語のテキスト and 🦀🦀").await;
+        harness.screen().assert_snapshot("text_area_wrapped");
+
+        // Enter starts a new line rather than finishing
+        harness.keys(ENTER).await;
+        harness.keys("ok").await;
+        harness.screen().assert_snapshot("text_area_new_line");
+
+        // up from column 2 lands after the first wide character, not in the middle of it
+        harness.keys(UP).await;
+        harness.keys("!").await;
+        harness.screen().assert_snapshot("text_area_moved_up");
+
+        harness.keys("\x04").await;
+        harness.wait_for("got").await;
+        harness.screen().assert_snapshot("text_area_done");
     }
 }
\ No newline at end of file
@@ -0,0 +1,67 @@
+use std::io::Write;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use async_trait::async_trait;
+use tokio::time::sleep;
+use crate::app::{AsyncWriter, OutputSink};
+
+/// Stands in for an SSH channel, counting what it's sent and optionally taking a while to send it.
+struct CountingSink {
+    bytes: Arc<AtomicU64>,
+    sends: Arc<AtomicU64>,
+    latency: Duration
+}
+
+#[async_trait]
+impl OutputSink for CountingSink {
+    async fn send(&mut self, data: Vec<u8>) -> std::io::Result<()> {
+        if !self.latency.is_zero() {
+            sleep(self.latency).await;
+        }
+
+        self.bytes.fetch_add(data.len() as u64, Ordering::Relaxed);
+        self.sends.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// Has `sessions` writers each flush `frames` frames of `frame_bytes` through [`AsyncWriter`]
+/// at once, and prints how long it took to get everything out.
+pub async fn bench_output(sessions: usize, frames: usize, frame_bytes: usize, latency: Duration) {
+    let bytes = Arc::new(AtomicU64::new(0));
+    let sends = Arc::new(AtomicU64::new(0));
+
+    let start = Instant::now();
+
+    let tasks: Vec<_> = (0..sessions).map(|_| {
+        let sink = CountingSink { bytes: bytes.clone(), sends: sends.clone(), latency };
+
+        tokio::spawn(async move {
+            let mut writer = AsyncWriter::new(sink);
+            let frame = vec![b'x'; frame_bytes];
+
+            for _ in 0..frames {
+                writer.write_all(&frame).unwrap();
+                writer.flush().unwrap();
+                writer.ready().await.unwrap();
+            }
+
+            writer.wait().await;
+        })
+    }).collect();
+
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    let elapsed = start.elapsed().as_secs_f64();
+    let bytes = bytes.load(Ordering::Relaxed);
+    let sends = sends.load(Ordering::Relaxed);
+    let total_frames = (sessions * frames) as f64;
+
+    println!("{sessions} sessions x {frames} frames of {frame_bytes} bytes, {}ms send latency", latency.as_millis());
+    println!("  took        {:.3}s", elapsed);
+    println!("  throughput  {:.1} MB/s, {:.0} frames/s", bytes as f64 / elapsed / 1_000_000.0, total_frames / elapsed);
+    println!("  batching    {} sends, {:.1} frames per send", sends, total_frames / sends.max(1) as f64);
+}
@@ -0,0 +1,272 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use dirs::home_dir;
+use glob::{glob_with, MatchOptions, Pattern};
+use serde::Deserialize;
+use tokio::fs;
+use tokio::process::Command;
+use tokio::time::timeout;
+
+/// The result of checking a crate against the submission criteria that can be verified
+/// automatically. Usefulness and uniqueness are still up to a human.
+pub struct CheckReport {
+    pub crate_name: String,
+    pub checks: Vec<Check>
+}
+
+pub struct Check {
+    pub name: &'static str,
+    pub passed: bool,
+    pub details: String
+}
+
+impl CheckReport {
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|check| check.passed)
+    }
+
+    fn push(&mut self, name: &'static str, passed: bool, details: impl Into<String>) -> bool {
+        self.checks.push(Check { name, passed, details: details.into() });
+        passed
+    }
+}
+
+impl Display for CheckReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Criteria check for {}: {}", self.crate_name, if self.passed() { "PASSED" } else { "FAILED" })?;
+
+        for check in &self.checks {
+            writeln!(f, "[{}] {}", if check.passed { "PASS" } else { "FAIL" }, check.name)?;
+            for line in check.details.lines() {
+                writeln!(f, "       {}", line)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+const HELP_TIMEOUT: Duration = Duration::from_secs(10);
+// big crates take a few minutes to build, so this is only for ones that never finish
+const INSTALL_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+// how much of a failed `cargo install`'s output is worth keeping in the report
+const INSTALL_LOG_LINES: usize = 15;
+
+/// Installs `crate_name` from crates.io into a throwaway directory and checks that it has a
+/// binary with a working `--help` and a README.
+pub async fn check_crate(crate_name: &str) -> CheckReport {
+    let root = std::env::temp_dir().join(format!("cargo-cult-check-{}-{}", crate_name, std::process::id()));
+    let report = run_checks(crate_name, &root).await;
+
+    let _ = fs::remove_dir_all(&root).await;
+
+    report
+}
+
+async fn run_checks(crate_name: &str, root: &Path) -> CheckReport {
+    let mut report = CheckReport { crate_name: crate_name.to_string(), checks: Vec::new() };
+
+    let install = Command::new("cargo")
+        .arg("install").arg("--root").arg(root).arg(crate_name)
+        .env("CARGO_TARGET_DIR", root.join("target"))
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    let installed = match timeout(INSTALL_TIMEOUT, install).await {
+        Ok(Ok(output)) if output.status.success() => report.push("Installs from crates.io", true, ""),
+        Ok(Ok(output)) => report.push("Installs from crates.io", false, log_tail(&String::from_utf8_lossy(&output.stderr))),
+        Ok(Err(e)) => report.push("Installs from crates.io", false, format!("Couldn't run cargo: {e}")),
+        Err(_) => report.push("Installs from crates.io", false, format!("`cargo install` didn't finish within {} minutes", INSTALL_TIMEOUT.as_secs() / 60))
+    };
+    if !installed {
+        return report;
+    }
+
+    let binaries = binaries(&root.join("bin")).await;
+    let Some(binary) = binaries.iter()
+        .find(|binary| binary.file_name().is_some_and(|name| name == crate_name))
+        .or(binaries.first()) else {
+        report.push("Has a binary", false, "The crate doesn't install any binaries");
+        return report;
+    };
+    let names: Vec<String> = binaries.iter().filter_map(|binary| binary.file_name()).map(|name| name.to_string_lossy().into_owned()).collect();
+    report.push("Has a binary", true, names.join(", "));
+
+    let help = Command::new(binary)
+        .arg("--help")
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    match timeout(HELP_TIMEOUT, help).await {
+        Ok(Ok(output)) => {
+            let text = String::from_utf8_lossy(&output.stdout).into_owned() + &String::from_utf8_lossy(&output.stderr);
+            if !output.status.success() {
+                report.push("Has a help page", false, format!("`--help` exited with {}", output.status));
+            } else if text.trim().is_empty() {
+                report.push("Has a help page", false, "`--help` didn't print anything");
+            } else {
+                report.push("Has a help page", true, "");
+            }
+        }
+        Ok(Err(e)) => { report.push("Has a help page", false, format!("Couldn't run the binary: {e}")); }
+        Err(_) => { report.push("Has a help page", false, format!("`--help` didn't exit within {} seconds", HELP_TIMEOUT.as_secs())); }
+    }
+
+    match readme(crate_name, root) {
+        Some(path) => report.push("Has a README", true, path.display().to_string()),
+        None => report.push("Has a README", false, "No README in the published crate")
+    };
+
+    report
+}
+
+/// The end of a failed install's log, where the error is.
+fn log_tail(log: &str) -> String {
+    let tail: Vec<&str> = log.lines().rev().take(INSTALL_LOG_LINES).collect();
+    tail.into_iter().rev().collect::<Vec<&str>>().join("\n")
+}
+
+async fn binaries(dir: &Path) -> Vec<PathBuf> {
+    let mut binaries = Vec::new();
+
+    if let Ok(mut entries) = fs::read_dir(dir).await {
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            binaries.push(entry.path());
+        }
+    }
+    binaries.sort();
+
+    binaries
+}
+
+pub fn cargo_home() -> Option<PathBuf> {
+    match std::env::var("CARGO_HOME") {
+        Ok(dir) => Some(PathBuf::from(dir)),
+        Err(_) => Some(home_dir()?.join(".cargo"))
+    }
+}
+
+/// Finds the README in the source of the version of `crate_name` installed in `install_root`
+/// (the cargo home, unless it was installed with `--root`), which `cargo install` leaves in the
+/// registry cache. Other versions can be there too, from other installs.
+pub fn readme(crate_name: &str, install_root: &Path) -> Option<PathBuf> {
+    let version = installed_version(install_root, crate_name)?;
+    find_readme(&cargo_home()?, crate_name, &version)
+}
+
+/// What `cargo install` keeps in `.crates2.json`, keyed by e.g.
+/// `ferris-says 0.3.1 (registry+https://github.com/rust-lang/crates.io-index)`.
+#[derive(Deserialize)]
+struct InstallRecords {
+    installs: HashMap<String, serde_json::Value>
+}
+
+fn installed_version(install_root: &Path, crate_name: &str) -> Option<String> {
+    let records = std::fs::read(install_root.join(".crates2.json")).ok()?;
+    let InstallRecords { installs } = serde_json::from_slice(&records).ok()?;
+
+    installs.keys().find_map(|package| {
+        let mut parts = package.split(' ');
+        (parts.next()? == crate_name).then(|| parts.next().map(str::to_string))?
+    })
+}
+
+fn find_readme(cargo_home: &Path, crate_name: &str, version: &str) -> Option<PathBuf> {
+    // the version is read from a file, so it mustn't be able to widen the pattern
+    let pattern = format!("{}/registry/src/*/{}-{}/README*", cargo_home.display(), crate_name, Pattern::escape(version));
+
+    let options = MatchOptions { case_sensitive: false, ..MatchOptions::new() };
+    glob_with(&pattern, options).ok()?.filter_map(Result::ok).next()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(results: &[(&'static str, bool, &str)]) -> CheckReport {
+        let mut report = CheckReport { crate_name: "ferris-says".to_string(), checks: Vec::new() };
+        for (name, passed, details) in results {
+            report.push(name, *passed, *details);
+        }
+        report
+    }
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cargo-cult-test-checker-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn reports_pass_only_when_every_check_does() {
+        assert!(report(&[]).passed());
+        assert!(report(&[("Installs from crates.io", true, ""), ("Has a binary", true, "ferris-says")]).passed());
+        assert!(!report(&[("Installs from crates.io", true, ""), ("Has a README", false, "")]).passed());
+    }
+
+    #[test]
+    fn reports_list_each_check_with_its_details() {
+        let report = report(&[
+            ("Installs from crates.io", true, ""),
+            ("Has a binary", true, "cowsay, ferris-says"),
+            ("Has a help page", false, "`--help` exited with exit status: 2\nsecond line")
+        ]);
+
+        assert_eq!(report.to_string(), "\
+Criteria check for ferris-says: FAILED
+[PASS] Installs from crates.io
+[PASS] Has a binary
+       cowsay, ferris-says
+[FAIL] Has a help page
+       `--help` exited with exit status: 2
+       second line
+");
+    }
+
+    #[test]
+    fn failed_installs_keep_the_end_of_the_log() {
+        let log: Vec<String> = (1..=20).map(|line| format!("line {line}")).collect();
+        let tail: Vec<String> = (6..=20).map(|line| format!("line {line}")).collect();
+
+        assert_eq!(log_tail(&log.join("\n")), tail.join("\n"));
+        assert_eq!(log_tail("error: no such crate\n"), "error: no such crate");
+        assert_eq!(log_tail(""), "");
+    }
+
+    #[test]
+    fn installed_versions_come_from_the_install_records() {
+        let root = scratch_dir("records");
+        assert_eq!(installed_version(&root, "ferris-says"), None);
+
+        std::fs::write(root.join(".crates2.json"), r#"{"installs":{
+            "ferris-says-extra 9.9.9 (registry+https://github.com/rust-lang/crates.io-index)":{},
+            "ferris-says 0.3.1 (registry+https://github.com/rust-lang/crates.io-index)":{"bins":["ferris-says"]}
+        }}"#).unwrap();
+
+        assert_eq!(installed_version(&root, "ferris-says").as_deref(), Some("0.3.1"));
+        assert_eq!(installed_version(&root, "ferris"), None);
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn readmes_come_from_the_installed_version() {
+        let home = scratch_dir("readmes");
+        let registry = home.join("registry/src/index.crates.io-6f17d22bba15001f");
+        for (version, readme) in [("0.2.0", "README.md"), ("0.3.1", "readme.md"), ("0.3.10", "README.md")] {
+            std::fs::create_dir_all(registry.join(format!("ferris-says-{version}"))).unwrap();
+            std::fs::write(registry.join(format!("ferris-says-{version}/{readme}")), version).unwrap();
+        }
+        std::fs::create_dir_all(registry.join("ferris-says-0.4.0")).unwrap();
+
+        assert_eq!(find_readme(&home, "ferris-says", "0.3.1"), Some(registry.join("ferris-says-0.3.1/readme.md")));
+        assert_eq!(find_readme(&home, "ferris-says", "0.4.0"), None);
+        assert_eq!(find_readme(&home, "ferris-says", "0.[23].*"), None);
+
+        let _ = std::fs::remove_dir_all(&home);
+    }
+}
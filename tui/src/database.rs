@@ -1,9 +1,14 @@
 use std::env;
-use std::error::Error;
+use std::fmt::{Debug, Formatter};
+use std::time::Instant;
+use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use crate::metrics::METRICS;
+use crate::pii::mask;
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
 pub struct FormData {
     #[serde(rename = "Type")]
     pub submission_type: String, // Submission | Update
@@ -38,7 +43,23 @@ pub struct FormData {
 
     #[serde(rename = "Package Name")]
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub package_name: Option<String>
+    pub package_name: Option<String>,
+
+    // record ids of the submission an update belongs to (an Airtable link field)
+    #[serde(rename = "Original Submission")]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub original_submission: Vec<String>,
+
+    #[serde(rename = "Review Status")]
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub review_status: String, // (blank) | Approved | Rejected
+    #[serde(rename = "Review Note")]
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub review_note: String,
+    // what `cargo-cult check` found, attached by a reviewer
+    #[serde(rename = "Check Report")]
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub check_report: String
 }
 
 impl FormData {
@@ -57,9 +78,98 @@ impl FormData {
             package_link: "".to_string(),
             description: "".to_string(),
             hours: "".to_string(),
-            package_name: None
+            package_name: None,
+            original_submission: Vec::new(),
+            review_status: "".to_string(),
+            review_note: "".to_string(),
+            check_report: "".to_string()
+        }
+    }
+
+    /// The crate's name, falling back to the end of the crates.io link for records Airtable
+    /// hasn't computed a package name for yet.
+    pub fn crate_name(&self) -> String {
+        self.package_name.clone().unwrap_or_else(||
+            self.package_link.trim_end_matches('/').rsplit('/').next().unwrap_or_default().to_string()
+        )
+    }
+
+    /// The record for an update to the submission `original_id`.
+    fn into_update(self, original_id: &str) -> Self {
+        Self {
+            submission_type: "Update".to_string(),
+            package_name: None, // computed by Airtable
+            original_submission: vec![original_id.to_string()],
+            ..self
         }
     }
+
+    /// Applies `f` to each field holding an email or postal address, e.g. to encrypt them.
+    pub fn map_pii(&self, f: impl Fn(&str) -> std::io::Result<String>) -> std::io::Result<Self> {
+        let mut result = self.clone();
+
+        for field in [
+            &mut result.email,
+            &mut result.address_line1,
+            &mut result.address_line2,
+            &mut result.city,
+            &mut result.state,
+            &mut result.zip,
+            &mut result.country
+        ] {
+            *field = f(field)?;
+        }
+
+        Ok(result)
+    }
+}
+
+// addresses and emails never end up in logs
+impl Debug for FormData {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FormData")
+            .field("submission_type", &self.submission_type)
+            .field("name", &self.name)
+            .field("slack_handle", &self.slack_handle)
+            .field("email", &mask(&self.email))
+            .field("address_line1", &mask(&self.address_line1))
+            .field("address_line2", &mask(&self.address_line2))
+            .field("city", &mask(&self.city))
+            .field("state", &mask(&self.state))
+            .field("zip", &mask(&self.zip))
+            .field("country", &mask(&self.country))
+            .field("package_link", &self.package_link)
+            .field("description", &self.description)
+            .field("hours", &self.hours)
+            .field("package_name", &self.package_name)
+            .field("original_submission", &self.original_submission)
+            .field("review_status", &self.review_status)
+            .field("review_note", &self.review_note)
+            .finish()
+    }
+}
+
+impl Default for FormData {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Where submissions are kept: Airtable, or memory in tests.
+#[async_trait]
+pub trait SubmissionStore: Send + Sync {
+    /// Approved submissions, as shown in the gallery.
+    async fn gallery(&self) -> anyhow::Result<Vec<FormData>>;
+    /// Original (non-update) submissions made by a Slack handle or email.
+    async fn find_by_submitter(&self, contact: &str) -> anyhow::Result<Vec<Record<FormData>>>;
+    /// Submissions nobody has approved or rejected yet.
+    async fn pending(&self) -> anyhow::Result<Vec<Record<FormData>>>;
+    async fn set_review(&self, id: &str, status: &str, note: &str) -> anyhow::Result<()>;
+    /// Attaches the output of `cargo-cult check` to a submission.
+    async fn set_check_report(&self, id: &str, report: &str) -> anyhow::Result<()>;
+    async fn create(&self, data: FormData) -> anyhow::Result<()>;
+    /// Adds an update to the submission `original_id`.
+    async fn update(&self, original_id: &str, data: FormData) -> anyhow::Result<()>;
 }
 
 pub struct SubmissionsAirtableBase {
@@ -76,10 +186,10 @@ const VIEW_NAME: &str = "Approved";
 
 // struct taken from the airtable-api crate
 #[derive(Debug, Clone, Serialize, Deserialize)]
-struct Record<T> {
+pub struct Record<T> {
     #[serde(default, skip_serializing_if = "String::is_empty")]
-    id: String,
-    fields: T,
+    pub id: String,
+    pub fields: T,
     #[serde(skip_serializing_if = "Option::is_none")]
     created_time: Option<DateTime<Utc>>,
 }
@@ -105,24 +215,265 @@ impl SubmissionsAirtableBase {
         }
     }
 
-    pub async fn get(&mut self) -> Result<Vec<FormData>, Box<dyn Error>> {
+    pub async fn get(&self) -> reqwest::Result<Vec<FormData>> {
 
-        let AirtableRecordsData { records } = self.client
+        let AirtableRecordsData { records } = self.send(self.client
             .get(format!("{AIRTABLE_BASE_URL}/{}/{}?maxRecords=100&view={}", self.base_id, self.table_name, self.view_name))
             .header("Authorization", format!("Bearer {}", self.airtable_key))
-            .send().await?.json().await?;
+        ).await?.json().await?;
 
         Ok(records.iter().map(|record| record.fields.clone()).collect())
     }
 
-    pub async fn create(&mut self, data: FormData) -> reqwest::Result<()> {
-        self.client
+    /// Finds the original (non-update) submissions made by a Slack handle or email, across all views.
+    pub async fn find_by_submitter(&self, contact: &str) -> reqwest::Result<Vec<Record<FormData>>> {
+        let needle = formula_string(&contact.trim().trim_start_matches('@').to_lowercase());
+        let formula = format!(
+            "AND(OR(LOWER(SUBSTITUTE({{Slack Handle}}, '@', '')) = {needle}, LOWER({{Email}}) = {needle}), NOT({{Original Submission}}))"
+        );
+
+        let AirtableRecordsData { records } = self.send(self.client
+            .get(format!("{AIRTABLE_BASE_URL}/{}/{}", self.base_id, self.table_name))
+            .query(&[("filterByFormula", formula.as_str()), ("maxRecords", "100")])
+            .header("Authorization", format!("Bearer {}", self.airtable_key))
+        ).await?.json().await?;
+
+        Ok(records)
+    }
+
+    /// Submissions nobody has approved or rejected yet, across all views. Ones approved before
+    /// review mode have no review status, but they're already in the gallery's view, so they're
+    /// left out.
+    pub async fn pending(&self) -> reqwest::Result<Vec<Record<FormData>>> {
+        let AirtableRecordsData { records } = self.send(self.client
+            .get(format!("{AIRTABLE_BASE_URL}/{}/{}", self.base_id, self.table_name))
+            .query(&[("filterByFormula", "NOT({Review Status})"), ("maxRecords", "100")])
+            .header("Authorization", format!("Bearer {}", self.airtable_key))
+        ).await?.json().await?;
+
+        let AirtableRecordsData { records: approved } = self.send(self.client
+            .get(format!("{AIRTABLE_BASE_URL}/{}/{}", self.base_id, self.table_name))
+            .query(&[("view", self.view_name.as_str()), ("filterByFormula", "NOT({Review Status})")])
+            .header("Authorization", format!("Bearer {}", self.airtable_key))
+        ).await?.json().await?;
+
+        Ok(records.into_iter().filter(|record| !approved.iter().any(|approved| approved.id == record.id)).collect())
+    }
+
+    /// Every submission (and update) for a crate, across all views.
+    pub async fn find_by_crate(&self, crate_name: &str) -> reqwest::Result<Vec<Record<FormData>>> {
+        let formula = format!("{{Package Name}} = {}", formula_string(crate_name));
+
+        let AirtableRecordsData { records } = self.send(self.client
+            .get(format!("{AIRTABLE_BASE_URL}/{}/{}", self.base_id, self.table_name))
+            .query(&[("filterByFormula", formula.as_str()), ("maxRecords", "100")])
+            .header("Authorization", format!("Bearer {}", self.airtable_key))
+        ).await?.json().await?;
+
+        Ok(records)
+    }
+
+    /// Records an admin's decision on a submission. This doesn't publish anything by itself: the
+    /// gallery reads the `Approved` view, so approvals only show up there once that view filters
+    /// on `{Review Status} = 'Approved'` (records approved before review mode can be added to the
+    /// filter with an OR).
+    pub async fn set_review(&self, id: &str, status: &str, note: &str) -> reqwest::Result<()> {
+        self.patch(id, serde_json::json!({
+            "Review Status": status,
+            "Review Note": note
+        })).await
+    }
+
+    /// Attaches the output of `cargo-cult check` to a submission.
+    pub async fn set_check_report(&self, id: &str, report: &str) -> reqwest::Result<()> {
+        self.patch(id, serde_json::json!({
+            "Check Report": report
+        })).await
+    }
+
+    async fn patch(&self, id: &str, fields: serde_json::Value) -> reqwest::Result<()> {
+        self.send(self.client
+            .patch(format!("{AIRTABLE_BASE_URL}/{}/{}/{}", self.base_id, self.table_name, id))
+            .header("Authorization", format!("Bearer {}", self.airtable_key))
+            .header("Content-Type", "application/json")
+            .json(&serde_json::json!({ "fields": fields }))
+        ).await?.error_for_status()?;
+        Ok(())
+    }
+
+    /// Submits an update to a project, as a new record linked to the original submission.
+    pub async fn update(&self, original_id: &str, data: FormData) -> reqwest::Result<()> {
+        self.create(data.into_update(original_id)).await
+    }
+
+    pub async fn create(&self, data: FormData) -> reqwest::Result<()> {
+        self.send(self.client
             .post(format!("{AIRTABLE_BASE_URL}/{}/{}", self.base_id, self.table_name))
             .header("Authorization", format!("Bearer {}", self.airtable_key))
             .header("Content-Type", "application/json")
             .json(&AirtableRecordsData {records: vec![Record {
                 id: String::new(), fields: data, created_time: None
-            }] }).send().await?;
+            }] })).await?;
         Ok(())
     }
+
+    /// Sends a request, keeping track of Airtable's latency and failures.
+    async fn send(&self, request: reqwest::RequestBuilder) -> reqwest::Result<reqwest::Response> {
+        let start = Instant::now();
+        let response = request.send().await;
+
+        METRICS.airtable_requests_total.inc();
+        METRICS.airtable_request_duration.observe(start.elapsed());
+        if !response.as_ref().is_ok_and(|response| response.status().is_success()) {
+            METRICS.airtable_errors_total.inc();
+        }
+
+        response
+    }
 }
+
+/// Quotes user input as a string literal for a `filterByFormula`. Backslashes go first, so
+/// one at the end of the input can't escape the closing quote.
+fn formula_string(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+#[async_trait]
+impl SubmissionStore for SubmissionsAirtableBase {
+    async fn gallery(&self) -> anyhow::Result<Vec<FormData>> {
+        Ok(self.get().await?)
+    }
+
+    async fn find_by_submitter(&self, contact: &str) -> anyhow::Result<Vec<Record<FormData>>> {
+        Ok(SubmissionsAirtableBase::find_by_submitter(self, contact).await?)
+    }
+
+    async fn pending(&self) -> anyhow::Result<Vec<Record<FormData>>> {
+        Ok(SubmissionsAirtableBase::pending(self).await?)
+    }
+
+    async fn set_review(&self, id: &str, status: &str, note: &str) -> anyhow::Result<()> {
+        Ok(SubmissionsAirtableBase::set_review(self, id, status, note).await?)
+    }
+
+    async fn set_check_report(&self, id: &str, report: &str) -> anyhow::Result<()> {
+        Ok(SubmissionsAirtableBase::set_check_report(self, id, report).await?)
+    }
+
+    async fn create(&self, data: FormData) -> anyhow::Result<()> {
+        Ok(SubmissionsAirtableBase::create(self, data).await?)
+    }
+
+    async fn update(&self, original_id: &str, data: FormData) -> anyhow::Result<()> {
+        Ok(SubmissionsAirtableBase::update(self, original_id, data).await?)
+    }
+}
+
+/// A [`SubmissionStore`] that behaves like the Airtable base, without the network.
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryStore {
+    records: std::sync::Mutex<Vec<Record<FormData>>>,
+    // ids of records in the `Approved` view from before review mode, without a review status
+    legacy_approvals: std::sync::Mutex<Vec<String>>
+}
+
+#[cfg(test)]
+impl MemoryStore {
+    pub fn with(submissions: Vec<FormData>) -> Self {
+        let store = Self::default();
+        for data in submissions {
+            store.insert(data);
+        }
+        store
+    }
+
+    /// Adds a submission that was approved by hand, before there was a review status to set.
+    pub fn approved_before_review(self, fields: FormData) -> Self {
+        let id = self.insert(fields);
+        self.legacy_approvals.lock().unwrap().push(id);
+        self
+    }
+
+    pub fn records(&self) -> Vec<Record<FormData>> {
+        self.records.lock().unwrap().clone()
+    }
+
+    fn insert(&self, fields: FormData) -> String {
+        let mut records = self.records.lock().unwrap();
+        let id = format!("rec{}", records.len() + 1);
+        records.push(Record { id: id.clone(), fields, created_time: None });
+        id
+    }
+
+    fn in_approved_view(&self, record: &Record<FormData>) -> bool {
+        record.fields.review_status == "Approved" || self.legacy_approvals.lock().unwrap().contains(&record.id)
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl SubmissionStore for MemoryStore {
+    // as the `Approved` view does, once it filters on the review status
+    async fn gallery(&self) -> anyhow::Result<Vec<FormData>> {
+        Ok(self.records().into_iter()
+            .filter(|record| self.in_approved_view(record))
+            .map(|record| record.fields)
+            .collect())
+    }
+
+    async fn find_by_submitter(&self, contact: &str) -> anyhow::Result<Vec<Record<FormData>>> {
+        let needle = contact.trim().trim_start_matches('@').to_lowercase();
+
+        Ok(self.records().into_iter()
+            .filter(|record| record.fields.original_submission.is_empty())
+            .filter(|record| {
+                record.fields.slack_handle.replace('@', "").to_lowercase() == needle || record.fields.email.to_lowercase() == needle
+            })
+            .collect())
+    }
+
+    async fn pending(&self) -> anyhow::Result<Vec<Record<FormData>>> {
+        Ok(self.records().into_iter()
+            .filter(|record| record.fields.review_status.is_empty() && !self.in_approved_view(record))
+            .collect())
+    }
+
+    async fn set_review(&self, id: &str, status: &str, note: &str) -> anyhow::Result<()> {
+        let mut records = self.records.lock().unwrap();
+        let record = records.iter_mut().find(|record| record.id == id).ok_or(anyhow::anyhow!("no record {id}"))?;
+        record.fields.review_status = status.to_string();
+        record.fields.review_note = note.to_string();
+        Ok(())
+    }
+
+    async fn set_check_report(&self, id: &str, report: &str) -> anyhow::Result<()> {
+        let mut records = self.records.lock().unwrap();
+        let record = records.iter_mut().find(|record| record.id == id).ok_or(anyhow::anyhow!("no record {id}"))?;
+        record.fields.check_report = report.to_string();
+        Ok(())
+    }
+
+    async fn create(&self, data: FormData) -> anyhow::Result<()> {
+        self.insert(data);
+        Ok(())
+    }
+
+    async fn update(&self, original_id: &str, data: FormData) -> anyhow::Result<()> {
+        self.insert(data.into_update(original_id));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::formula_string;
+
+    #[test]
+    fn formula_strings_cant_be_escaped() {
+        assert_eq!(formula_string("fiona"), "'fiona'");
+        assert_eq!(formula_string("o'brien"), r"'o\'brien'");
+        assert_eq!(formula_string(r"x\"), r"'x\\'");
+        assert_eq!(formula_string(r"x\') OR TRUE() OR ('"), r"'x\\\') OR TRUE() OR (\''");
+    }
+}
\ No newline at end of file
@@ -0,0 +1,474 @@
+use std::time::Duration;
+use async_trait::async_trait;
+use clap::{ColorChoice, Parser, Subcommand};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::Receiver;
+use tokio::time::timeout;
+use tracing::info;
+use crate::database::{FormData, SubmissionStore};
+use crate::limits::{LimitReached, LIMITS};
+use crate::metrics::METRICS;
+use crate::shutdown;
+use crate::ssh_client::{self, shell_quote, SANDBOX_HOST, SANDBOX_KEY, SANDBOX_USER};
+use crate::TerminalParams;
+
+/// Commands that can be run without a terminal, for scripting against the server, e.g.
+/// `ssh cargo-cult.example list --json`.
+#[derive(Debug, Parser)]
+#[command(name = "cargo-cult", no_binary_name = true, disable_version_flag = true, color = ColorChoice::Never)]
+struct ExecCommand {
+    #[command(subcommand)]
+    command: Command
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Lists the projects in the gallery
+    #[command(alias = "gallery")]
+    List {
+        #[arg(long)]
+        json: bool
+    },
+    /// Shows one project from the gallery
+    Info {
+        #[arg(index = 1)]
+        crate_name: String,
+        #[arg(long)]
+        json: bool
+    },
+    /// Prints a project's README
+    Readme {
+        #[arg(index = 1)]
+        crate_name: String,
+        #[arg(long)]
+        json: bool
+    },
+    /// Sends a new submission, read from stdin
+    Submit {
+        /// Read the submission as a JSON object. This is the only format for now
+        #[arg(long, required = true)]
+        json: bool
+    }
+}
+
+/// What a command printed, and the status it exited with.
+pub struct Output {
+    pub stdout: String,
+    pub stderr: String,
+    pub exit_status: u32
+}
+
+impl Output {
+    fn success(stdout: impl Into<String>) -> Self {
+        Self { stdout: stdout.into(), stderr: String::new(), exit_status: 0 }
+    }
+
+    fn failure(stderr: impl Into<String>) -> Self {
+        Self { stdout: String::new(), stderr: stderr.into(), exit_status: 1 }
+    }
+}
+
+/// A gallery entry, without anything that isn't already public on the site.
+#[derive(Serialize)]
+struct Project {
+    #[serde(rename = "crate")]
+    crate_name: String,
+    author: String,
+    link: String,
+    description: String
+}
+
+impl From<&FormData> for Project {
+    fn from(data: &FormData) -> Self {
+        Self {
+            crate_name: data.crate_name(),
+            author: data.name.clone(),
+            link: data.package_link.clone(),
+            description: data.description.clone()
+        }
+    }
+}
+
+/// The fields of a submission someone fills in themselves, named the way they'd be in a script.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct SubmissionInput {
+    name: String,
+    slack_handle: String,
+    email: String,
+    address_line1: String,
+    address_line2: String,
+    city: String,
+    state: String,
+    zip: String,
+    country: String,
+    package_link: String,
+    description: String,
+    hours: String
+}
+
+/// Where `readme` finds READMEs.
+#[async_trait]
+pub trait Readmes: Send + Sync {
+    /// The README of `crate_name`, or `None` if it hasn't got one.
+    async fn readme(&self, crate_name: &str) -> Result<Option<String>, String>;
+}
+
+/// The READMEs in the sandbox image, which is the only place the gallery's crates are installed.
+pub struct SandboxReadmes;
+
+#[async_trait]
+impl Readmes for SandboxReadmes {
+    async fn readme(&self, crate_name: &str) -> Result<Option<String>, String> {
+        let command = format!("docker run --rm --entrypoint cargo-cult cargo-cult print-readme {}", shell_quote(crate_name));
+        let output = ssh_client::output(SANDBOX_KEY, SANDBOX_USER, SANDBOX_HOST, &command);
+
+        match timeout(README_TIMEOUT, output).await {
+            Ok(Ok((0, stdout))) => Ok(Some(String::from_utf8_lossy(&stdout).into_owned())),
+            // print-readme's way of saying there's no README
+            Ok(Ok((1, _))) => Ok(None),
+            Ok(Ok((status, _))) => Err(format!("the sandbox exited with {status}")),
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(_) => Err("the sandbox took too long to answer".to_string())
+        }
+    }
+}
+
+// a submission is a few hundred bytes, so anything near this is a mistake
+const MAX_STDIN_BYTES: usize = 64 * 1024;
+// a container starts in a second or two, so this is only for one that's stuck
+const README_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What a command prints instead of running when the connection went over a limit.
+pub fn refuse(limit: &LimitReached) -> Output {
+    Output::failure(format!("{} Please try again in {}.\n", limit.reason, limit.retry_after_text()))
+}
+
+/// Runs a command sent with `ssh <host> <command>`. `stdin` is whatever the client sends before
+/// closing its side of the channel; commands that don't read it drop it straight away.
+pub async fn run(
+    command_line: &str,
+    params: &TerminalParams,
+    store: &dyn SubmissionStore,
+    readmes: &dyn Readmes,
+    stdin: Receiver<Vec<u8>>
+) -> Output {
+    // crate names and flags never need quoting, so there's no point in a real shell parser
+    let command = match ExecCommand::try_parse_from(command_line.split_whitespace()) {
+        Ok(ExecCommand { command }) => command,
+        // --help ends up here too, and goes to stdout with a status of 0
+        Err(error) => {
+            let text = error.render().to_string();
+            let (stdout, stderr) = if error.use_stderr() { (String::new(), text) } else { (text, String::new()) };
+            return Output { stdout, stderr, exit_status: error.exit_code() as u32 };
+        }
+    };
+
+    // only submit reads stdin, so the others close it rather than leave a client waiting to send
+    match command {
+        Command::List { json } => { drop(stdin); list(store, json).await }
+        Command::Info { crate_name, json } => { drop(stdin); project_info(store, &crate_name, json).await }
+        Command::Readme { crate_name, json } => { drop(stdin); readme(store, readmes, &crate_name, json).await }
+        Command::Submit { .. } => submit(params, store, stdin).await
+    }
+}
+
+async fn gallery(store: &dyn SubmissionStore) -> Result<Vec<FormData>, Output> {
+    let projects = store.gallery().await;
+    METRICS.gallery_fetches_total.inc();
+
+    projects.map_err(|error| Output::failure(format!("Couldn't load the gallery: {error}\n")))
+}
+
+async fn find_project(store: &dyn SubmissionStore, crate_name: &str) -> Result<FormData, Output> {
+    gallery(store).await?.into_iter()
+        .find(|project| project.crate_name() == crate_name)
+        .ok_or_else(|| Output::failure(format!("There's no project called {crate_name} in the gallery.\n")))
+}
+
+async fn list(store: &dyn SubmissionStore, json: bool) -> Output {
+    let projects = match gallery(store).await {
+        Ok(projects) => projects,
+        Err(output) => return output
+    };
+    let projects: Vec<Project> = projects.iter().map(Project::from).collect();
+
+    if json {
+        return Output::success(serde_json::to_string_pretty(&projects).unwrap() + "\n");
+    }
+
+    let width = projects.iter().map(|project| project.crate_name.len()).max().unwrap_or(0);
+    let mut text = String::new();
+    for project in projects {
+        let summary = project.description.lines().next().unwrap_or_default();
+        text += &format!("{:width$}  {}\n", project.crate_name, summary);
+    }
+    Output::success(text)
+}
+
+async fn project_info(store: &dyn SubmissionStore, crate_name: &str, json: bool) -> Output {
+    let project = match find_project(store, crate_name).await {
+        Ok(project) => Project::from(&project),
+        Err(output) => return output
+    };
+
+    if json {
+        return Output::success(serde_json::to_string_pretty(&project).unwrap() + "\n");
+    }
+
+    Output::success(format!(
+        "{} by {}\n{}\n\n{}\n",
+        project.crate_name, project.author, project.link, project.description.trim_end()
+    ))
+}
+
+async fn readme(store: &dyn SubmissionStore, readmes: &dyn Readmes, crate_name: &str, json: bool) -> Output {
+    // only gallery projects, so this can't be used to poke around the registry
+    if let Err(output) = find_project(store, crate_name).await {
+        return output;
+    }
+
+    let text = match readmes.readme(crate_name).await {
+        Ok(Some(text)) => text,
+        Ok(None) => return Output::failure(format!("{crate_name} doesn't have a README.\n")),
+        Err(error) => return Output::failure(format!("Couldn't read the README for {crate_name}: {error}\n"))
+    };
+
+    if json {
+        let readme = serde_json::json!({ "crate": crate_name, "readme": text });
+        return Output::success(serde_json::to_string_pretty(&readme).unwrap() + "\n");
+    }
+
+    Output::success(text)
+}
+
+async fn submit(params: &TerminalParams, store: &dyn SubmissionStore, mut stdin: Receiver<Vec<u8>>) -> Output {
+    let mut input = Vec::new();
+    while let Some(data) = stdin.recv().await {
+        input.extend(data);
+        if input.len() > MAX_STDIN_BYTES {
+            return Output::failure("That's too much input for a submission.\n");
+        }
+    }
+
+    // only once the input's all here, so a client that never closes stdin can't hold up a restart
+    let _submitting = shutdown::submitting();
+
+    let input: SubmissionInput = match serde_json::from_slice(&input) {
+        Ok(input) => input,
+        Err(error) => return Output::failure(format!("Couldn't read the submission: {error}\n"))
+    };
+
+    let required = [
+        ("name", &input.name),
+        ("slack_handle", &input.slack_handle),
+        ("email", &input.email),
+        ("address_line1", &input.address_line1),
+        ("city", &input.city),
+        ("state", &input.state),
+        ("zip", &input.zip),
+        ("country", &input.country),
+        ("package_link", &input.package_link),
+        ("description", &input.description),
+        ("hours", &input.hours)
+    ];
+    let missing: Vec<&str> = required.iter().filter(|(_, value)| value.trim().is_empty()).map(|(field, _)| *field).collect();
+    if !missing.is_empty() {
+        return Output::failure(format!("The submission is missing: {}\n", missing.join(", ")));
+    }
+
+    if let Err(limit) = LIMITS.submit(&params.rate_limit_key()) {
+        return refuse(&limit);
+    }
+
+    let data = FormData {
+        name: input.name,
+        slack_handle: input.slack_handle,
+        email: input.email,
+        address_line1: input.address_line1,
+        address_line2: input.address_line2,
+        city: input.city,
+        state: input.state,
+        zip: input.zip,
+        country: input.country,
+        package_link: input.package_link,
+        description: input.description,
+        hours: input.hours,
+        ..FormData::new()
+    };
+
+    let crate_name = data.crate_name();
+    if let Err(error) = store.create(data).await {
+        return Output::failure(format!("Couldn't send the submission: {error}\n"));
+    }
+    info!(crate_name, "submission created");
+    METRICS.submissions_total.inc();
+
+    let reply = serde_json::json!({ "crate": crate_name, "status": "submitted" });
+    Output::success(serde_json::to_string_pretty(&reply).unwrap() + "\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use tokio::sync::mpsc;
+    use crate::database::{FormData, MemoryStore};
+    use super::*;
+
+    fn params(username: &str) -> TerminalParams {
+        TerminalParams {
+            term: String::new(),
+            col_width: 80,
+            row_height: 24,
+            modes: Vec::new(),
+            username: username.to_string(),
+            key_fingerprint: None,
+            peer_ip: None
+        }
+    }
+
+    #[async_trait]
+    impl Readmes for HashMap<&'static str, &'static str> {
+        async fn readme(&self, crate_name: &str) -> Result<Option<String>, String> {
+            Ok(self.get(crate_name).map(|text| text.to_string()))
+        }
+    }
+
+    /// Runs `command_line` as `username` (each test has its own, for the rate limits), with
+    /// `stdin` sent in and then closed.
+    async fn exec(command_line: &str, username: &str, store: &MemoryStore, stdin: &str) -> Output {
+        exec_with_readmes(command_line, username, store, &HashMap::new(), stdin).await
+    }
+
+    async fn exec_with_readmes(command_line: &str, username: &str, store: &MemoryStore, readmes: &dyn Readmes, stdin: &str) -> Output {
+        let (sender, receiver) = mpsc::channel(1);
+        let stdin = stdin.as_bytes().to_vec();
+        let sending = tokio::spawn(async move {
+            // a command that doesn't read stdin closes it, which is fine
+            let _ = sender.send(stdin).await;
+        });
+
+        let output = run(command_line, &params(username), store, readmes, receiver).await;
+        sending.await.unwrap();
+        output
+    }
+
+    fn approved(crate_name: &str) -> FormData {
+        FormData {
+            name: "Fiona".to_string(),
+            package_link: format!("https://crates.io/crates/{crate_name}"),
+            package_name: Some(crate_name.to_string()),
+            description: format!("{crate_name} does a thing.\nAnd another."),
+            review_status: "Approved".to_string(),
+            ..FormData::new()
+        }
+    }
+
+    const SUBMISSION: &str = r#"{
+        "name": "Fiona", "slack_handle": "@fiona", "email": "fiona@example.com",
+        "address_line1": "15 Falls Rd", "city": "Shelburne", "state": "VT", "zip": "05482",
+        "country": "USA", "package_link": "https://crates.io/crates/ferris-says",
+        "description": "Says things.", "hours": "12"
+    }"#;
+
+    #[tokio::test]
+    async fn parses_command_lines() {
+        let store = MemoryStore::with(vec![approved("ferris-says")]);
+
+        let cases = [
+            ("list", 0, "ferris-says  ferris-says does a thing.\n"),
+            ("gallery", 0, "ferris-says  ferris-says does a thing.\n"),
+            ("  info   ferris-says  ", 0, "ferris-says by Fiona\n"),
+            ("info not-here", 1, ""),
+            ("", 2, ""),
+            ("frobnicate", 2, ""),
+            ("info", 2, ""),
+            ("submit", 2, ""),
+            ("list --yaml", 2, "")
+        ];
+
+        for (command_line, exit_status, stdout) in cases {
+            let output = exec(command_line, "exec-parsing", &store, "").await;
+            assert_eq!(output.exit_status, exit_status, "{command_line:?}: {}", output.stderr);
+            assert!(output.stdout.starts_with(stdout), "{command_line:?}: {}", output.stdout);
+            assert_eq!(output.stderr.is_empty(), exit_status == 0, "{command_line:?}");
+        }
+
+        // help goes to stdout, like it would for any other command
+        let help = exec("--help", "exec-parsing", &store, "").await;
+        assert_eq!(help.exit_status, 0);
+        assert!(help.stdout.contains("Lists the projects in the gallery"));
+    }
+
+    #[tokio::test]
+    async fn lists_projects_as_json() {
+        let store = MemoryStore::with(vec![approved("ferris-says")]);
+
+        let output = exec("list --json", "exec-json", &store, "").await;
+        let projects: serde_json::Value = serde_json::from_str(&output.stdout).unwrap();
+        assert_eq!(projects[0]["crate"], "ferris-says");
+        assert_eq!(projects[0]["author"], "Fiona");
+        // nothing that isn't public already
+        assert!(projects[0].get("email").is_none());
+    }
+
+    #[tokio::test]
+    async fn prints_readmes_of_gallery_projects() {
+        let pending = FormData { review_status: String::new(), ..approved("pending") };
+        let store = MemoryStore::with(vec![approved("ferris-says"), approved("no-readme"), pending]);
+        let readmes = HashMap::from([("ferris-says", "# ferris-says\n\nSays things.\n"), ("pending", "# pending\n")]);
+
+        let cases = [
+            ("readme ferris-says", 0, "# ferris-says\n\nSays things.\n", ""),
+            ("readme no-readme", 1, "", "no-readme doesn't have a README.\n"),
+            // only what's in the gallery, even if there's a README for it
+            ("readme pending", 1, "", "There's no project called pending in the gallery.\n")
+        ];
+
+        for (command_line, exit_status, stdout, stderr) in cases {
+            let output = exec_with_readmes(command_line, "exec-readme", &store, &readmes, "").await;
+            assert_eq!((output.exit_status, output.stdout.as_str(), output.stderr.as_str()), (exit_status, stdout, stderr), "{command_line:?}");
+        }
+
+        let output = exec_with_readmes("readme ferris-says --json", "exec-readme", &store, &readmes, "").await;
+        let readme: serde_json::Value = serde_json::from_str(&output.stdout).unwrap();
+        assert_eq!(readme["crate"], "ferris-says");
+        assert_eq!(readme["readme"], "# ferris-says\n\nSays things.\n");
+    }
+
+    #[tokio::test]
+    async fn submits_from_stdin() {
+        let store = MemoryStore::default();
+
+        let output = exec("submit --json", "exec-submit", &store, SUBMISSION).await;
+        assert_eq!(output.exit_status, 0, "{}", output.stderr);
+        assert!(output.stdout.contains(r#""status": "submitted""#));
+
+        let records = store.records();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].fields.email, "fiona@example.com");
+        assert_eq!(records[0].fields.crate_name(), "ferris-says");
+    }
+
+    #[tokio::test]
+    async fn refuses_invalid_submissions() {
+        let store = MemoryStore::default();
+        let too_long = format!(r#"{{"description": "{}"}}"#, "a".repeat(MAX_STDIN_BYTES));
+
+        let cases = [
+            ("", "Couldn't read the submission"),
+            ("not json", "Couldn't read the submission"),
+            (r#"{"name": "Fiona", "favorite_color": "red"}"#, "unknown field `favorite_color`"),
+            (r#"{"name": "Fiona", "email": "  "}"#, "missing: slack_handle, email, address_line1, city, state, zip, country, package_link, description, hours"),
+            (&too_long, "That's too much input")
+        ];
+
+        for (stdin, error) in cases {
+            let output = exec("submit --json", "exec-invalid", &store, stdin).await;
+            assert_eq!(output.exit_status, 1);
+            assert!(output.stderr.contains(error), "{stdin:.40}: {}", output.stderr);
+        }
+
+        assert!(store.records().is_empty());
+    }
+}
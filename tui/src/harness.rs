@@ -0,0 +1,226 @@
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use async_trait::async_trait;
+use tokio::sync::mpsc::{self, Sender};
+use tokio::task::{AbortHandle, JoinHandle};
+use tokio::time::{sleep, Instant};
+use crate::app::{App, OutputSink};
+use crate::database::MemoryStore;
+use crate::route::Route;
+use crate::pii::PiiCipher;
+use crate::screen::Screen;
+use crate::storage::LocalStore;
+use crate::terminal::TerminalDecoder;
+use crate::{TerminalCode, TerminalParams};
+
+pub const COLS: usize = 80;
+pub const ROWS: usize = 24;
+
+// virtual time, so this only matters when something never shows up
+const WAIT_TIMEOUT: Duration = Duration::from_secs(60);
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Runs an [`App`] with no terminal attached: keys are typed in as bytes, and what it writes is
+/// drawn on a [`Screen`]. Meant for `#[tokio::test(start_paused = true)]`, so the app's sleeps
+/// don't slow tests down.
+pub struct Harness {
+    pub store: Arc<MemoryStore>,
+    pub local: Arc<LocalStore>,
+    data_dir: Arc<DataDir>,
+    screen: Arc<Mutex<Screen>>,
+    input: Sender<TerminalCode>,
+    decoder: TerminalDecoder,
+    exited: Arc<AtomicBool>,
+    task: JoinHandle<()>
+}
+
+/// Who a harness connects as. By default that's someone without an SSH key, with a username of
+/// their own so tests don't share rate limits.
+#[derive(Clone)]
+pub struct Client {
+    pub username: String,
+    pub key_fingerprint: Option<String>
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self::named(&format!("test-user-{}", NEXT_ID.fetch_add(1, Ordering::Relaxed)))
+    }
+}
+
+impl Client {
+    pub fn named(username: &str) -> Self {
+        Self { username: username.to_string(), key_fingerprint: None }
+    }
+
+    pub fn with_key(self, key_fingerprint: &str) -> Self {
+        Self { key_fingerprint: Some(key_fingerprint.to_string()), ..self }
+    }
+}
+
+/// Where a harness's local store keeps its files, removed once no session uses it.
+struct DataDir(PathBuf);
+
+impl Drop for DataDir {
+    fn drop(&mut self) {
+        // it's only there if a test saved something
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+pub struct ScreenSink(Arc<Mutex<Screen>>);
+
+/// The kind of [`App`] a harness runs, for tests that call its methods directly.
+pub type TestApp = App<ScreenSink, Box<dyn FnOnce() + Send + Sync>>;
+
+#[async_trait]
+impl OutputSink for ScreenSink {
+    async fn send(&mut self, data: Vec<u8>) -> std::io::Result<()> {
+        self.0.lock().unwrap().feed(&data);
+        Ok(())
+    }
+}
+
+async fn run_route(mut app: TestApp, route: Route) {
+    app.route(route).await.expect("the app to run without errors");
+}
+
+impl Harness {
+    /// Starts the app at `route`. Every harness gets a local store in a temporary directory of
+    /// its own, so tests don't share drafts (or touch the real ones).
+    pub fn start(route: Route, store: MemoryStore) -> Self {
+        Self::connect(Client::default(), route, store)
+    }
+
+    /// Like [`Self::start`], as `client`.
+    pub fn connect(client: Client, route: Route, store: MemoryStore) -> Self {
+        Self::launch(COLS, ROWS, client, Arc::new(store), Self::temporary_local_store(), |app| run_route(app, route))
+    }
+
+    /// Starts another session on the same server, sharing this one's stores, as someone
+    /// coming back later (or someone else entirely) would.
+    pub fn reconnect(&self, client: Client, route: Route) -> Self {
+        let local = (self.local.clone(), self.data_dir.clone());
+        Self::launch(COLS, ROWS, client, self.store.clone(), local, |app| run_route(app, route))
+    }
+
+    /// Runs `f` with an app in a terminal of `cols` by `rows`, for testing one part of the app
+    /// (say, a single prompt) on its own.
+    pub fn run<Fut>(cols: usize, rows: usize, store: MemoryStore, f: impl FnOnce(TestApp) -> Fut) -> Self
+    where
+        Fut: Future<Output = ()> + Send + 'static
+    {
+        Self::launch(cols, rows, Client::default(), Arc::new(store), Self::temporary_local_store(), f)
+    }
+
+    fn temporary_local_store() -> (Arc<LocalStore>, Arc<DataDir>) {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let dir = std::env::temp_dir().join(format!("cargo-cult-test-{}-{id}", std::process::id()));
+
+        (Arc::new(LocalStore::at(dir.clone(), PiiCipher::random())), Arc::new(DataDir(dir)))
+    }
+
+    fn launch<Fut>(
+        cols: usize,
+        rows: usize,
+        client: Client,
+        store: Arc<MemoryStore>,
+        (local, data_dir): (Arc<LocalStore>, Arc<DataDir>),
+        f: impl FnOnce(TestApp) -> Fut
+    ) -> Self
+    where
+        Fut: Future<Output = ()> + Send + 'static
+    {
+        let screen = Arc::new(Mutex::new(Screen::new(cols, rows)));
+        let (input, receiver) = mpsc::channel(64);
+
+        let params = TerminalParams {
+            term: "xterm-256color".to_string(),
+            col_width: cols as u32,
+            row_height: rows as u32,
+            modes: Vec::new(),
+            username: client.username,
+            key_fingerprint: client.key_fingerprint,
+            peer_ip: None
+        };
+
+        // the app spins once it's exited, waiting to be dropped, so it stops itself instead
+        let exited = Arc::new(AtomicBool::new(false));
+        let app_handle: Arc<Mutex<Option<AbortHandle>>> = Arc::default();
+        let exit = {
+            let exited = exited.clone();
+            let app_handle = app_handle.clone();
+            move || {
+                exited.store(true, Ordering::Relaxed);
+                if let Some(handle) = app_handle.lock().unwrap().take() {
+                    handle.abort();
+                }
+            }
+        };
+
+        let app: TestApp = App::new(
+            ScreenSink(screen.clone()),
+            receiver,
+            Arc::new(tokio::sync::Mutex::new(params)),
+            store.clone(),
+            local.clone(),
+            Box::new(exit)
+        );
+        let task = tokio::spawn(f(app));
+        *app_handle.lock().unwrap() = Some(task.abort_handle());
+
+        Self { store, local, data_dir, screen, input, decoder: TerminalDecoder::new(), exited, task }
+    }
+
+    /// Types `keys` as a terminal would send them, e.g. `"\x1b[B\r"` for down and Enter.
+    pub async fn keys(&mut self, keys: &str) {
+        for code in self.decoder.decode(keys.as_bytes()) {
+            self.input.send(code).await.expect("the app to still be reading input");
+            self.settle().await;
+        }
+    }
+
+    /// Lets the app catch up with everything sent to it so far.
+    pub async fn settle(&self) {
+        sleep(POLL_INTERVAL).await;
+    }
+
+    /// Waits until `text` is somewhere on the screen.
+    pub async fn wait_for(&self, text: &str) {
+        let deadline = Instant::now() + WAIT_TIMEOUT;
+
+        while !self.screen.lock().unwrap().contains(text) {
+            if Instant::now() > deadline || self.task.is_finished() {
+                panic!("{text:?} never showed up on the screen:\n{}", self.screen.lock().unwrap());
+            }
+            sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Waits until the app has exited, as it does when the user is done with a route.
+    pub async fn wait_for_exit(&self) {
+        let deadline = Instant::now() + WAIT_TIMEOUT;
+
+        while !self.exited.load(Ordering::Relaxed) {
+            if Instant::now() > deadline || self.task.is_finished() {
+                panic!("the app never exited:\n{}", self.screen.lock().unwrap());
+            }
+            sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    pub fn screen(&self) -> std::sync::MutexGuard<'_, Screen> {
+        self.screen.lock().unwrap()
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
@@ -0,0 +1,451 @@
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::futures::Notified;
+use tokio::sync::Notify;
+
+/// Caps on how much of the server one person (or everyone together) can use at once.
+///
+/// Every limit can be changed with an environment variable, e.g. `CARGO_CULT_MAX_SANDBOXES=10`.
+pub struct Limits {
+    max_connections: usize,
+    max_connections_per_ip: usize,
+    max_sandboxes: usize,
+    max_sandboxes_per_ip: usize,
+    sandbox_launches: BucketConfig,
+    submissions: BucketConfig,
+
+    state: Mutex<State>,
+    // woken whenever a sandbox slot frees up or the queue moves
+    sandbox_changes: Notify
+}
+
+#[derive(Default)]
+struct State {
+    connections: Usage,
+    sandboxes: Usage,
+    buckets: HashMap<(Bucket, String), TokenBucket>,
+
+    // tickets waiting for a sandbox, oldest first
+    sandbox_queue: VecDeque<u64>,
+    average_sandbox_duration: Option<Duration>
+}
+
+/// Concurrent use of something, in total and per IP.
+#[derive(Default)]
+struct Usage {
+    total: usize,
+    per_ip: HashMap<IpAddr, usize>
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Bucket {
+    SandboxLaunches,
+    Submissions
+}
+
+#[derive(Clone, Copy)]
+struct BucketConfig {
+    capacity: f64,
+    refill_every: Duration
+}
+
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant
+}
+
+/// Why something was refused, and roughly when it's worth trying again.
+#[derive(Clone, Debug)]
+pub struct LimitReached {
+    pub reason: &'static str,
+    pub retry_after: Duration
+}
+
+/// Held for as long as a connection or sandbox is running. Dropping it frees the slot.
+pub struct Permit {
+    limits: Arc<Limits>,
+    kind: PermitKind,
+    ip: Option<IpAddr>
+}
+
+#[derive(Clone, Copy)]
+enum PermitKind {
+    Connection,
+    Sandbox { started: Instant }
+}
+
+/// A place in line for a sandbox, from [`Limits::queue_sandbox`]. Dropping it leaves the queue.
+pub struct Ticket {
+    limits: Arc<Limits>,
+    id: u64,
+    ip: Option<IpAddr>
+}
+
+pub enum SandboxSlot {
+    Ready(Permit),
+    Waiting { position: usize, estimated_wait: Duration },
+    Refused(LimitReached)
+}
+
+static NEXT_TICKET_ID: AtomicU64 = AtomicU64::new(0);
+
+// there's no telling when someone else will leave, so this is just a suggestion
+const BUSY_RETRY: Duration = Duration::from_secs(5 * 60);
+// a guess at how long sandboxes last until there's been one to measure
+const DEFAULT_SANDBOX_DURATION: Duration = Duration::from_secs(5 * 60);
+
+pub static LIMITS: LazyLock<Arc<Limits>> = LazyLock::new(|| Arc::new(Limits::from_env()));
+
+fn from_env<T: FromStr>(name: &str, default: T) -> T {
+    env::var(name).ok().and_then(|value| value.parse().ok()).unwrap_or(default)
+}
+
+impl Limits {
+    fn from_env() -> Self {
+        Self {
+            max_connections: from_env("CARGO_CULT_MAX_CONNECTIONS", 200),
+            max_connections_per_ip: from_env("CARGO_CULT_MAX_CONNECTIONS_PER_IP", 5),
+            max_sandboxes: from_env("CARGO_CULT_MAX_SANDBOXES", 20),
+            max_sandboxes_per_ip: from_env("CARGO_CULT_MAX_SANDBOXES_PER_IP", 2),
+            sandbox_launches: BucketConfig {
+                capacity: from_env("CARGO_CULT_SANDBOX_BURST", 5.0),
+                refill_every: Duration::from_secs(from_env("CARGO_CULT_SANDBOX_REFILL_SECS", 120))
+            },
+            submissions: BucketConfig {
+                capacity: from_env("CARGO_CULT_SUBMISSION_BURST", 3.0),
+                refill_every: Duration::from_secs(from_env("CARGO_CULT_SUBMISSION_REFILL_SECS", 600))
+            },
+            state: Mutex::new(State::default()),
+            sandbox_changes: Notify::new()
+        }
+    }
+
+    pub fn connect(self: &Arc<Self>, ip: Option<IpAddr>) -> Result<Permit, LimitReached> {
+        let mut state = self.state.lock().unwrap();
+
+        if state.connections.total >= self.max_connections {
+            return Err(LimitReached { reason: "The server is full.", retry_after: BUSY_RETRY });
+        }
+        if state.connections.count(ip) >= self.max_connections_per_ip {
+            return Err(LimitReached { reason: "There are too many connections from your network.", retry_after: BUSY_RETRY });
+        }
+
+        state.connections.add(ip);
+        Ok(Permit { limits: Arc::clone(self), kind: PermitKind::Connection, ip })
+    }
+
+    /// Gets in line for a sandbox, using up one of `identity`'s sandbox launches. `identity` is
+    /// whoever the user can be recognized as: their key, or their IP address if they didn't use one.
+    pub fn queue_sandbox(self: &Arc<Self>, ip: Option<IpAddr>, identity: &str) -> Result<Ticket, LimitReached> {
+        let mut state = self.state.lock().unwrap();
+
+        if let Err(retry_after) = state.take_token(Bucket::SandboxLaunches, self.sandbox_launches, identity, Instant::now()) {
+            return Err(LimitReached { reason: "You've started a lot of sandboxes recently.", retry_after });
+        }
+
+        let id = NEXT_TICKET_ID.fetch_add(1, Ordering::Relaxed);
+        state.sandbox_queue.push_back(id);
+        Ok(Ticket { limits: Arc::clone(self), id, ip })
+    }
+
+    /// Starts the ticket's sandbox if it's at the front of the line and there's room.
+    pub fn try_start_sandbox(self: &Arc<Self>, ticket: &Ticket) -> SandboxSlot {
+        let mut state = self.state.lock().unwrap();
+
+        if state.sandboxes.count(ticket.ip) >= self.max_sandboxes_per_ip {
+            return SandboxSlot::Refused(LimitReached { reason: "Your network already has too many sandboxes running.", retry_after: BUSY_RETRY });
+        }
+
+        // a ticket that's already been used (or never queued) mustn't take someone else's turn
+        let Some(position) = state.sandbox_queue.iter().position(|&id| id == ticket.id) else {
+            return SandboxSlot::Refused(LimitReached { reason: "You're no longer in line for a sandbox.", retry_after: Duration::ZERO });
+        };
+        if position == 0 && state.sandboxes.total < self.max_sandboxes {
+            state.sandbox_queue.remove(position);
+            state.sandboxes.add(ticket.ip);
+            self.sandbox_changes.notify_waiters();
+
+            return SandboxSlot::Ready(Permit { limits: Arc::clone(self), kind: PermitKind::Sandbox { started: Instant::now() }, ip: ticket.ip });
+        }
+
+        // everyone ahead needs a slot, and each slot frees up every average session or so
+        let average = state.average_sandbox_duration.unwrap_or(DEFAULT_SANDBOX_DURATION);
+        let estimated_wait = average * (position as u32 + 1) / self.max_sandboxes.max(1) as u32;
+
+        SandboxSlot::Waiting { position: position + 1, estimated_wait }
+    }
+
+    /// Resolves the next time the sandbox queue might have moved. Call before
+    /// [`Self::try_start_sandbox`] so a change in between isn't missed.
+    pub fn sandbox_changed(&self) -> Notified<'_> {
+        self.sandbox_changes.notified()
+    }
+
+    pub fn submit(&self, identity: &str) -> Result<(), LimitReached> {
+        let mut state = self.state.lock().unwrap();
+
+        state.take_token(Bucket::Submissions, self.submissions, identity, Instant::now())
+            .map_err(|retry_after| LimitReached { reason: "You've sent a lot of submissions recently.", retry_after })
+    }
+}
+
+impl State {
+    fn take_token(&mut self, kind: Bucket, config: BucketConfig, identity: &str, now: Instant) -> Result<(), Duration> {
+        let refill_rate = 1.0 / config.refill_every.as_secs_f64();
+
+        // forget buckets that have filled back up, so the map doesn't grow forever
+        self.buckets.retain(|(bucket_kind, _), bucket| {
+            *bucket_kind != kind || bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * refill_rate < config.capacity
+        });
+
+        let bucket = self.buckets.entry((kind, identity.to_string()))
+            .or_insert(TokenBucket { tokens: config.capacity, updated: now });
+        bucket.tokens = (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * refill_rate).min(config.capacity);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / refill_rate))
+        }
+    }
+}
+
+impl Usage {
+    fn count(&self, ip: Option<IpAddr>) -> usize {
+        ip.and_then(|ip| self.per_ip.get(&ip).copied()).unwrap_or(0)
+    }
+
+    fn add(&mut self, ip: Option<IpAddr>) {
+        self.total += 1;
+        if let Some(ip) = ip {
+            *self.per_ip.entry(ip).or_insert(0) += 1;
+        }
+    }
+
+    fn remove(&mut self, ip: Option<IpAddr>) {
+        self.total -= 1;
+        if let Some(ip) = ip {
+            if let Some(count) = self.per_ip.get_mut(&ip) {
+                *count -= 1;
+                if *count == 0 {
+                    self.per_ip.remove(&ip);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let mut state = self.limits.state.lock().unwrap();
+
+        match self.kind {
+            PermitKind::Connection => state.connections.remove(self.ip),
+            PermitKind::Sandbox { started } => {
+                state.sandboxes.remove(self.ip);
+
+                let duration = started.elapsed();
+                state.average_sandbox_duration = Some(match state.average_sandbox_duration {
+                    Some(average) => average.mul_f64(0.8) + duration.mul_f64(0.2),
+                    None => duration
+                });
+                self.limits.sandbox_changes.notify_waiters();
+            }
+        }
+    }
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        let mut state = self.limits.state.lock().unwrap();
+
+        let length = state.sandbox_queue.len();
+        state.sandbox_queue.retain(|&id| id != self.id);
+        if state.sandbox_queue.len() != length {
+            self.limits.sandbox_changes.notify_waiters();
+        }
+    }
+}
+
+impl LimitReached {
+    pub fn retry_after_text(&self) -> String {
+        minutes(self.retry_after)
+    }
+}
+
+/// "N minutes", rounded up, for telling the user how long something will take.
+pub fn minutes(duration: Duration) -> String {
+    let minutes = duration.as_secs().div_ceil(60).max(1);
+    format!("{} minute{}", minutes, if minutes == 1 { "" } else { "s" })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+    use tokio::sync::Notify;
+    use super::{Bucket, BucketConfig, LimitReached, Limits, SandboxSlot, State};
+
+    fn limits() -> Arc<Limits> {
+        Arc::new(Limits {
+            max_connections: 3,
+            max_connections_per_ip: 2,
+            max_sandboxes: 2,
+            max_sandboxes_per_ip: 1,
+            sandbox_launches: BucketConfig { capacity: 2.0, refill_every: Duration::from_secs(60) },
+            submissions: BucketConfig { capacity: 1.0, refill_every: Duration::from_secs(600) },
+            state: Mutex::new(State::default()),
+            sandbox_changes: Notify::new()
+        })
+    }
+
+    fn ip(last: u8) -> Option<IpAddr> {
+        Some([192, 0, 2, last].into())
+    }
+
+    fn reason<T>(result: Result<T, LimitReached>) -> &'static str {
+        result.err().expect("should have been refused").reason
+    }
+
+    #[test]
+    fn connections_are_capped_per_ip_and_in_total() {
+        let limits = limits();
+
+        let first = limits.connect(ip(1)).unwrap();
+        let _second = limits.connect(ip(1)).unwrap();
+        assert_eq!(reason(limits.connect(ip(1))), "There are too many connections from your network.");
+
+        let _third = limits.connect(ip(2)).unwrap();
+        assert_eq!(reason(limits.connect(ip(3))), "The server is full.");
+
+        drop(first);
+        assert!(limits.connect(ip(1)).is_ok());
+    }
+
+    #[test]
+    fn sandboxes_are_capped_per_ip_and_in_total() {
+        let limits = limits();
+
+        let ticket = limits.queue_sandbox(ip(1), "first").unwrap();
+        let SandboxSlot::Ready(first) = limits.try_start_sandbox(&ticket) else { panic!("the first sandbox should start") };
+
+        let ticket = limits.queue_sandbox(ip(1), "second").unwrap();
+        let SandboxSlot::Refused(limit) = limits.try_start_sandbox(&ticket) else { panic!("a second sandbox from one IP should be refused") };
+        assert_eq!(limit.reason, "Your network already has too many sandboxes running.");
+        drop(ticket);
+
+        let ticket = limits.queue_sandbox(ip(2), "third").unwrap();
+        let SandboxSlot::Ready(_third) = limits.try_start_sandbox(&ticket) else { panic!("there's still room for another IP") };
+
+        let ticket = limits.queue_sandbox(ip(3), "fourth").unwrap();
+        assert!(matches!(limits.try_start_sandbox(&ticket), SandboxSlot::Waiting { position: 1, .. }));
+
+        drop(first);
+        assert!(matches!(limits.try_start_sandbox(&ticket), SandboxSlot::Ready(_)));
+    }
+
+    #[test]
+    fn the_queue_goes_in_order() {
+        let limits = limits();
+        let running = [ip(1), ip(2)].map(|ip| {
+            let ticket = limits.queue_sandbox(ip, "running").unwrap();
+            let SandboxSlot::Ready(permit) = limits.try_start_sandbox(&ticket) else { panic!("there should be room") };
+            permit
+        });
+
+        let tickets: Vec<_> = (3..6).map(|last| limits.queue_sandbox(ip(last), &last.to_string()).unwrap()).collect();
+        let waits: Vec<_> = tickets.iter().map(|ticket| match limits.try_start_sandbox(ticket) {
+            SandboxSlot::Waiting { position, estimated_wait } => (position, estimated_wait),
+            _ => panic!("everyone should be waiting")
+        }).collect();
+
+        // two slots, each freeing up every five minutes or so
+        assert_eq!(waits, [
+            (1, Duration::from_secs(150)),
+            (2, Duration::from_secs(300)),
+            (3, Duration::from_secs(450))
+        ]);
+
+        // only the front of the line can take a free slot
+        drop(running);
+        assert!(matches!(limits.try_start_sandbox(&tickets[1]), SandboxSlot::Waiting { position: 2, .. }));
+        assert!(matches!(limits.try_start_sandbox(&tickets[0]), SandboxSlot::Ready(_)));
+        assert!(matches!(limits.try_start_sandbox(&tickets[2]), SandboxSlot::Waiting { position: 2, .. }));
+        assert!(matches!(limits.try_start_sandbox(&tickets[1]), SandboxSlot::Ready(_)));
+    }
+
+    #[test]
+    fn dropped_tickets_leave_the_queue() {
+        let limits = limits();
+        let ticket = limits.queue_sandbox(ip(1), "running").unwrap();
+        let SandboxSlot::Ready(_running) = limits.try_start_sandbox(&ticket) else { panic!("there should be room") };
+        let ticket = limits.queue_sandbox(ip(2), "running").unwrap();
+        let SandboxSlot::Ready(_running_too) = limits.try_start_sandbox(&ticket) else { panic!("there should be room") };
+
+        let first = limits.queue_sandbox(ip(3), "first").unwrap();
+        let second = limits.queue_sandbox(ip(4), "second").unwrap();
+        assert!(matches!(limits.try_start_sandbox(&second), SandboxSlot::Waiting { position: 2, .. }));
+
+        drop(first);
+        assert!(matches!(limits.try_start_sandbox(&second), SandboxSlot::Waiting { position: 1, .. }));
+    }
+
+    #[test]
+    fn used_tickets_dont_take_someone_elses_turn() {
+        let limits = limits();
+        let ticket = limits.queue_sandbox(ip(1), "first").unwrap();
+        let SandboxSlot::Ready(_permit) = limits.try_start_sandbox(&ticket) else { panic!("there should be room") };
+        let waiting = limits.queue_sandbox(ip(2), "second").unwrap();
+
+        assert!(matches!(limits.try_start_sandbox(&ticket), SandboxSlot::Refused(_)));
+        assert_eq!(limits.state.lock().unwrap().sandboxes.total, 1);
+        assert!(matches!(limits.try_start_sandbox(&waiting), SandboxSlot::Ready(_)));
+    }
+
+    #[test]
+    fn token_buckets_refill_over_time() {
+        let config = BucketConfig { capacity: 2.0, refill_every: Duration::from_secs(60) };
+        let mut state = State::default();
+        let start = Instant::now();
+
+        assert!(state.take_token(Bucket::Submissions, config, "someone", start).is_ok());
+        assert!(state.take_token(Bucket::Submissions, config, "someone", start).is_ok());
+        assert_eq!(state.take_token(Bucket::Submissions, config, "someone", start), Err(Duration::from_secs(60)));
+
+        // other people and other kinds of bucket aren't affected
+        assert!(state.take_token(Bucket::Submissions, config, "someone else", start).is_ok());
+        assert!(state.take_token(Bucket::SandboxLaunches, config, "someone", start).is_ok());
+
+        assert_eq!(state.take_token(Bucket::Submissions, config, "someone", start + Duration::from_secs(30)), Err(Duration::from_secs(30)));
+        assert!(state.take_token(Bucket::Submissions, config, "someone", start + Duration::from_secs(60)).is_ok());
+    }
+
+    #[test]
+    fn launches_and_submissions_are_rate_limited() {
+        let limits = limits();
+
+        let _first = limits.queue_sandbox(None, "someone").unwrap();
+        let _second = limits.queue_sandbox(None, "someone").unwrap();
+        assert_eq!(reason(limits.queue_sandbox(None, "someone")), "You've started a lot of sandboxes recently.");
+
+        assert!(limits.submit("someone").is_ok());
+        assert_eq!(reason(limits.submit("someone")), "You've sent a lot of submissions recently.");
+        assert!(limits.submit("someone else").is_ok());
+    }
+
+    #[test]
+    fn retry_after_text() {
+        for (seconds, text) in [(0, "1 minute"), (60, "1 minute"), (61, "2 minutes"), (600, "10 minutes")] {
+            let limit = LimitReached { reason: "", retry_after: Duration::from_secs(seconds) };
+            assert_eq!(limit.retry_after_text(), text, "{seconds}s");
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::{HashMap, VecDeque};
+use std::ops::Deref;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use rand::Rng;
+use tokio::sync::{broadcast, mpsc};
+
+/// A sandbox session that other users can watch live, found by its short code. Anyone with the
+/// separate invite code can also join in and type into it.
+pub struct LiveSession {
+    pub watch_code: String,
+    pub invite_code: String,
+    pub host: String,
+
+    events: broadcast::Sender<LiveEvent>,
+    // recent output, replayed to viewers who join partway through
+    scrollback: Mutex<VecDeque<u8>>,
+
+    guest_input: mpsc::UnboundedSender<Vec<u8>>,
+    guest_input_receiver: Mutex<Option<mpsc::UnboundedReceiver<Vec<u8>>>>,
+    guests: Mutex<Vec<Participant>>
+}
+
+/// Someone driving a shared session alongside the host.
+#[derive(Clone)]
+pub struct Participant {
+    pub name: String,
+    pub col_width: u32,
+    pub row_height: u32,
+
+    id: u64
+}
+
+/// The host's hold on a [`LiveSession`]. The session ends when this is dropped, however the
+/// host's sandbox stops, so nobody is left watching one that's gone.
+pub struct Hosting(Arc<LiveSession>);
+
+/// A guest's place in a [`LiveSession`]. They're removed from it when this is dropped.
+pub struct Guest {
+    session: Arc<LiveSession>,
+    id: u64
+}
+
+#[derive(Clone)]
+pub enum LiveEvent {
+    Output(Vec<u8>),
+    Ended
+}
+
+// no 0/O or 1/I, so codes can be read out loud
+const CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const CODE_LENGTH: usize = 6;
+const SCROLLBACK_BYTES: usize = 64 * 1024;
+const EVENT_BUFFER: usize = 256;
+
+static NEXT_GUEST_ID: AtomicU64 = AtomicU64::new(0);
+
+static SESSIONS: LazyLock<Mutex<HashMap<String, Arc<LiveSession>>>> = LazyLock::new(Default::default);
+
+impl LiveSession {
+    /// Makes a new session watchable. It stays listed until the [`Hosting`] is dropped.
+    pub fn start(host: &str) -> Hosting {
+        let mut sessions = SESSIONS.lock().unwrap();
+
+        let unused_code = || loop {
+            let code = Self::random_code();
+            if !sessions.contains_key(&code) && !sessions.values().any(|session| session.invite_code == code) { break code }
+        };
+        let watch_code = unused_code();
+        let invite_code = unused_code();
+
+        let (events, _) = broadcast::channel(EVENT_BUFFER);
+        let (guest_input, guest_input_receiver) = mpsc::unbounded_channel();
+        let session = Arc::new(Self {
+            watch_code: watch_code.clone(),
+            invite_code,
+            host: host.to_string(),
+            events,
+            scrollback: Mutex::new(VecDeque::new()),
+            guest_input,
+            guest_input_receiver: Mutex::new(Some(guest_input_receiver)),
+            guests: Mutex::new(Vec::new())
+        });
+
+        sessions.insert(watch_code, session.clone());
+        Hosting(session)
+    }
+
+    pub fn find(watch_code: &str) -> Option<Arc<Self>> {
+        SESSIONS.lock().unwrap().get(&watch_code.trim().to_uppercase()).cloned()
+    }
+
+    pub fn find_invite(invite_code: &str) -> Option<Arc<Self>> {
+        let invite_code = invite_code.trim().to_uppercase();
+        SESSIONS.lock().unwrap().values().find(|session| session.invite_code == invite_code).cloned()
+    }
+
+    fn end(&self) {
+        SESSIONS.lock().unwrap().remove(&self.watch_code);
+        let _ = self.events.send(LiveEvent::Ended);
+    }
+
+    /// Sends output from the host's terminal to everyone watching.
+    pub fn broadcast(&self, data: &[u8]) {
+        {
+            let mut scrollback = self.scrollback.lock().unwrap();
+            scrollback.extend(data);
+            let excess = scrollback.len().saturating_sub(SCROLLBACK_BYTES);
+            scrollback.drain(..excess);
+        }
+
+        let _ = self.events.send(LiveEvent::Output(Vec::from(data)));
+    }
+
+    /// Sends something redrawn over and over, like the status line, to everyone watching. It's
+    /// left out of the scrollback, since it would soon push out everything else.
+    pub fn broadcast_transient(&self, data: &[u8]) {
+        let _ = self.events.send(LiveEvent::Output(Vec::from(data)));
+    }
+
+    /// Recent output to catch a new viewer up, and a receiver for everything after it.
+    pub fn watch(&self) -> (Vec<u8>, broadcast::Receiver<LiveEvent>) {
+        let scrollback = self.scrollback.lock().unwrap();
+        (scrollback.iter().copied().collect(), self.events.subscribe())
+    }
+
+    pub fn viewers(&self) -> usize {
+        self.events.receiver_count()
+    }
+
+    /// Adds a guest with a terminal of the given size. Their input goes to the host's session
+    /// through [`Guest::send`].
+    pub fn join(self: &Arc<Self>, name: &str, col_width: u32, row_height: u32) -> Guest {
+        let id = NEXT_GUEST_ID.fetch_add(1, Ordering::Relaxed);
+        self.guests.lock().unwrap().push(Participant { name: name.to_string(), col_width, row_height, id });
+
+        Guest { session: self.clone(), id }
+    }
+
+    pub fn guests(&self) -> Vec<Participant> {
+        self.guests.lock().unwrap().clone()
+    }
+
+    /// Input typed by guests. Only the host's session can take it, and only once.
+    pub fn take_guest_input(&self) -> Option<mpsc::UnboundedReceiver<Vec<u8>>> {
+        self.guest_input_receiver.lock().unwrap().take()
+    }
+
+    fn random_code() -> String {
+        let mut rng = rand::thread_rng();
+        (0..CODE_LENGTH).map(|_| CODE_ALPHABET[rng.gen_range(0..CODE_ALPHABET.len())] as char).collect()
+    }
+}
+
+impl Deref for Hosting {
+    type Target = Arc<LiveSession>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl Drop for Hosting {
+    fn drop(&mut self) {
+        self.0.end();
+    }
+}
+
+impl Guest {
+    pub fn send(&self, input: &[u8]) {
+        let _ = self.session.guest_input.send(Vec::from(input));
+    }
+}
+
+impl Drop for Guest {
+    fn drop(&mut self) {
+        self.session.guests.lock().unwrap().retain(|guest| guest.id != self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sessions_end_when_the_host_lets_go() {
+        let hosting = LiveSession::start("fiona");
+        let watch_code = hosting.watch_code.clone();
+        let (_, mut events) = LiveSession::find(&watch_code).unwrap().watch();
+
+        drop(hosting);
+
+        assert!(LiveSession::find(&watch_code).is_none());
+        assert!(matches!(events.try_recv(), Ok(LiveEvent::Ended)));
+    }
+
+    #[test]
+    fn transient_output_isnt_replayed() {
+        let hosting = LiveSession::start("fiona");
+        let (_, mut events) = hosting.watch();
+
+        hosting.broadcast(b"output");
+        hosting.broadcast_transient(b"status");
+
+        assert!(matches!(events.try_recv(), Ok(LiveEvent::Output(data)) if data == b"output"));
+        assert!(matches!(events.try_recv(), Ok(LiveEvent::Output(data)) if data == b"status"));
+        assert_eq!(hosting.watch().0, b"output");
+    }
+}
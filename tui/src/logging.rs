@@ -0,0 +1,25 @@
+use std::env;
+use tracing_subscriber::EnvFilter;
+
+// russh logs every packet at info
+const SERVER_FILTER: &str = "info,russh=warn";
+// everything else shares stderr with the TUI (or a command's output), so only problems
+const LOCAL_FILTER: &str = "warn";
+
+/// Sends logs to stderr. `CARGO_CULT_LOG` sets the level, using `RUST_LOG`'s syntax
+/// (e.g. `debug` or `cargo_cult=debug,russh=warn`); by default that's `info` for the server and
+/// `warn` for everything else. `CARGO_CULT_LOG_FORMAT` picks the output: one line per event by
+/// default, `pretty` for multi-line events or `json` for one JSON object per event.
+pub fn init(server: bool) {
+    let default_filter = if server { SERVER_FILTER } else { LOCAL_FILTER };
+    let filter = EnvFilter::try_from_env("CARGO_CULT_LOG").unwrap_or(EnvFilter::new(default_filter));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+
+    match env::var("CARGO_CULT_LOG_FORMAT").as_deref() {
+        Ok("json") => builder.json().init(),
+        Ok("pretty") => builder.pretty().init(),
+        _ => builder.init()
+    }
+}
@@ -1,22 +1,43 @@
+use std::net::IpAddr;
 use std::process::{exit, Stdio};
 use std::sync::Arc;
+use std::time::Duration;
 use clap::{Parser, Subcommand};
-use dirs::home_dir;
 
 use dotenv::dotenv;
-use glob::glob;
 use russh::Pty;
 use tokio::process::Command;
 use tokio::sync::Mutex;
+use crate::bench::bench_output;
+use crate::checker::{cargo_home, check_crate, readme};
 use crate::database::SubmissionsAirtableBase;
+use crate::route::Route;
 
 use crate::ssh_server::ssh_server;
+use crate::storage::LocalStore;
 use crate::terminal::{make_terminal_app};
 
+mod bench;
+mod checker;
 mod database;
 mod app;
+mod exec;
+#[cfg(test)]
+mod harness;
+mod limits;
+mod live;
+mod logging;
+mod metrics;
+mod notices;
+mod pii;
+mod recording;
+mod route;
+#[cfg(test)]
+mod screen;
+mod shutdown;
 mod ssh_client;
 mod ssh_server;
+mod storage;
 mod terminal;
 
 #[tokio::main]
@@ -28,13 +49,14 @@ async fn main() {
         SubCommand::CargoCult { command } => command,
         SubCommand::Action(action) => action
     };
+    logging::init(matches!(action, Action::Ssh));
 
     match action {
         Action::Ssh => {
             ssh_server().await
         }
         Action::InstallAllPackages => {
-            let mut airtable = SubmissionsAirtableBase::new();
+            let airtable = SubmissionsAirtableBase::new();
             let packages: Vec<String> = airtable.get().await.unwrap().iter().map(|entry| entry.package_name.clone().unwrap()).collect();
 
             Command::new("cargo")
@@ -46,37 +68,127 @@ async fn main() {
             println!("Welcome! Run '{package_name}' to test out {author}'s CLI! Or, run 'readme {package_name}' to view the readme.");
             println!("This Ubuntu VM will self-destruct in 30 minutes. Run 'exit' to exit.");
             println!("psst: all the other projects are installed here, so feel free to try them out.");
-            Command::new("bash")
-                .env("PS1", format!("{}@cargo-cult:\\w\\$ ", username))
-                .arg("--noprofile").arg("--norc")
-                .stdin(Stdio::inherit())
-                .stdout(Stdio::inherit())
-                .stderr(Stdio::inherit())
+            shell(&username).await;
+        }
+        Action::ReviewEntrypoint { username, package_name, readme } => {
+            // submissions under review aren't baked into the image, so install them first
+            println!("Installing {package_name} from crates.io...");
+            let status = Command::new("cargo")
+                .arg("install").arg("--quiet").arg(&package_name)
                 .spawn().expect("TODO").wait().await.unwrap();
+            if !status.success() {
+                eprintln!("Could not install {package_name}!");
+                exit(1);
+            }
+
+            if readme {
+                show_readme(&package_name).await;
+            } else {
+                println!("Installed! Run '{package_name}' to test it, or 'readme {package_name}' to view the readme.");
+                println!("This Ubuntu VM will self-destruct in 30 minutes. Run 'exit' to exit.");
+                shell(&username).await;
+            }
         }
-        Action::Readme { package_name } => {
-            let Some(Ok(path)) = glob(
-                format!("{}/.cargo/registry/src/*/{}*/README.md", home_dir().unwrap().display(), package_name).as_str()
-            ).unwrap().next() else {
-                eprintln!("Could not find package README!");
+        Action::Check { crate_name, attach } => {
+            let report = check_crate(&crate_name).await;
+            print!("{report}");
+
+            if attach {
+                let airtable = SubmissionsAirtableBase::new();
+                let records = airtable.find_by_crate(&crate_name).await.unwrap();
+                // only submissions still waiting on a decision get the report
+                let pending: Vec<_> = records.iter().filter(|record| record.fields.review_status.is_empty()).collect();
+                if pending.is_empty() {
+                    eprintln!("No pending submission for {crate_name} to attach the report to.");
+                }
+                for record in pending {
+                    airtable.set_check_report(&record.id, &report.to_string()).await.unwrap();
+                    println!("Attached the report to {}.", record.id);
+                }
+            }
+
+            if !report.passed() {
                 exit(1);
-            };
-
-            Command::new("glow")
-                .arg(path.display().to_string())
-                .arg("-p")
-                .stdin(Stdio::inherit())
-                .stdout(Stdio::inherit())
-                .stderr(Stdio::inherit())
-                .spawn().expect("TODO").wait().await.unwrap();
+            }
+        }
+        Action::PrintReadme { crate_name } => {
+            match cargo_home().and_then(|home| readme(&crate_name, &home)).map(std::fs::read_to_string) {
+                Some(Ok(text)) => print!("{text}"),
+                Some(Err(e)) => {
+                    eprintln!("Could not read the README: {e}");
+                    exit(2);
+                }
+                None => {
+                    eprintln!("{crate_name} has no README.");
+                    exit(1);
+                }
+            }
+        }
+        Action::RevealPii => {
+            let (drafts, identities) = open_local_store().reveal_all().await.unwrap_or_else(|e| {
+                eprintln!("Could not decrypt the local store: {e}");
+                exit(1);
+            });
+
+            println!("{}", serde_json::to_string_pretty(&serde_json::json!({
+                "drafts": drafts,
+                "identities": identities
+            })).unwrap());
+        }
+        Action::Broadcast { message } => {
+            match notices::send(&message).await {
+                Ok(reply) => print!("{reply}"),
+                Err(e) => {
+                    eprintln!("Could not reach the server: {e}");
+                    exit(1);
+                }
+            }
         }
-        _ => {
+        Action::BenchOutput { sessions, frames, frame_bytes, latency_ms } => {
+            bench_output(sessions, frames, frame_bytes, Duration::from_millis(latency_ms)).await
+        }
+        Action::Route(Route::Readme { package_name }) => show_readme(&package_name).await,
+        Action::Route(route) => {
             let mut app = make_terminal_app().await;
-            app.run().await.unwrap();
+            app.route(route).await.unwrap();
         }
     }
 }
 
+/// Opens the local store, or exits if it's misconfigured (say, with a malformed
+/// `CARGO_CULT_PII_KEY`), so that's found once at startup instead of in every session.
+pub fn open_local_store() -> LocalStore {
+    LocalStore::new().unwrap_or_else(|e| {
+        eprintln!("Could not open the local store: {e}");
+        exit(1);
+    })
+}
+
+async fn shell(username: &str) {
+    Command::new("bash")
+        .env("PS1", format!("{}@cargo-cult:\\w\\$ ", username))
+        .arg("--noprofile").arg("--norc")
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .spawn().expect("TODO").wait().await.unwrap();
+}
+
+async fn show_readme(package_name: &str) {
+    let Some(path) = cargo_home().and_then(|home| readme(package_name, &home)) else {
+        eprintln!("Could not find package README!");
+        exit(1);
+    };
+
+    Command::new("glow")
+        .arg(path.display().to_string())
+        .arg("-p")
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .spawn().expect("TODO").wait().await.unwrap();
+}
+
 #[derive(Debug, Parser)]
 #[clap(multicall = true)]
 struct Cli {
@@ -99,8 +211,17 @@ enum SubCommand {
 enum Action {
     Ssh,
 
-    Menu,
-    Gallery,
+    #[command(flatten)]
+    Route(Route),
+
+    /// Installs a crate in a scratch directory and checks it against the submission criteria
+    Check {
+        #[arg(index = 1)]
+        crate_name: String,
+        /// Attach the report to the crate's pending submissions in Airtable
+        #[arg(long)]
+        attach: bool
+    },
 
     #[command(hide = true)]
     InstallAllPackages,
@@ -114,9 +235,41 @@ enum Action {
         author: String
     },
     #[command(hide = true)]
-    Readme {
+    ReviewEntrypoint {
         #[arg(index = 1)]
-        package_name: String
+        username: String,
+        #[arg(index = 2)]
+        package_name: String,
+        #[arg(long)]
+        readme: bool
+    },
+    /// Prints an installed crate's README as it is, exiting with 1 if there isn't one
+    #[command(hide = true)]
+    PrintReadme {
+        #[arg(index = 1)]
+        crate_name: String
+    },
+    /// Prints the locally stored drafts and identities with emails and addresses decrypted
+    #[command(hide = true)]
+    RevealPii,
+    /// Shows a message to everyone connected to the server running on this machine
+    #[command(hide = true)]
+    Broadcast {
+        #[arg(index = 1)]
+        message: String
+    },
+    /// Measures output throughput with many sessions writing at once
+    #[command(hide = true)]
+    BenchOutput {
+        #[arg(long, default_value_t = 500)]
+        sessions: usize,
+        #[arg(long, default_value_t = 1000)]
+        frames: usize,
+        #[arg(long, default_value_t = 64)]
+        frame_bytes: usize,
+        /// How long each send to a (simulated) client takes
+        #[arg(long, default_value_t = 0)]
+        latency_ms: u64
     }
 }
 
@@ -126,7 +279,21 @@ struct TerminalParams {
     col_width: u32,
     row_height: u32,
     modes: Vec<(Pty, u32)>,
-    username: String
+    username: String,
+    key_fingerprint: Option<String>,
+    peer_ip: Option<IpAddr>
+}
+
+impl TerminalParams {
+    /// Who the user is as far as rate limits go. Anyone can pick a different username, so
+    /// without a key it's their IP address.
+    fn rate_limit_key(&self) -> String {
+        match (&self.key_fingerprint, self.peer_ip) {
+            (Some(key_fingerprint), _) => key_fingerprint.clone(),
+            (None, Some(ip)) => format!("ip:{ip}"),
+            (None, None) => format!("user:{}", self.username)
+        }
+    }
 }
 
 type SharedTerminalParams = Arc<Mutex<TerminalParams>>;
@@ -134,15 +301,38 @@ type SharedTerminalParams = Arc<Mutex<TerminalParams>>;
 #[derive(Clone)]
 struct TerminalCode {
     ascii_code: Option<AsciiCode>,
+    modifiers: KeyModifiers,
     raw_bytes: Vec<u8>
 }
 
-#[derive(PartialEq, Clone)]
+#[derive(PartialEq, Clone, Debug)]
 enum AsciiCode {
-    Char(u8),
+    Char(char),
+    Ctrl(char),
+    Alt(char),
     Backspace,
     Enter,
+    Tab,
+    BackTab,
+    Esc,
     ArrowDown,
     ArrowUp,
+    ArrowLeft,
+    ArrowRight,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Insert,
+    Delete,
+    F(u8),
+    Paste(String),
     EoT
 }
+
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+struct KeyModifiers {
+    shift: bool,
+    alt: bool,
+    ctrl: bool
+}
@@ -0,0 +1,286 @@
+use std::env;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{info, warn};
+
+/// Counters for the whole server, served in Prometheus' text format by [`serve`].
+pub struct Metrics {
+    pub ssh_connections: Gauge,
+    pub ssh_connections_total: Counter,
+    pub ssh_session_duration: Histogram,
+    pub sandbox_sessions: Gauge,
+    pub sandbox_session_duration: Histogram,
+    pub submissions_total: Counter,
+    pub gallery_fetches_total: Counter,
+    pub airtable_requests_total: Counter,
+    pub airtable_errors_total: Counter,
+    pub airtable_request_duration: Histogram
+}
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(|| Metrics {
+    ssh_connections: Gauge::new("cargo_cult_ssh_connections", "SSH connections currently open"),
+    ssh_connections_total: Counter::new("cargo_cult_ssh_connections_total", "SSH connections accepted"),
+    ssh_session_duration: Histogram::new("cargo_cult_ssh_session_duration_seconds", "How long SSH connections stayed open", SESSION_BUCKETS),
+    sandbox_sessions: Gauge::new("cargo_cult_sandbox_sessions", "Sandbox containers currently running"),
+    sandbox_session_duration: Histogram::new("cargo_cult_sandbox_session_duration_seconds", "How long sandbox sessions lasted", SESSION_BUCKETS),
+    submissions_total: Counter::new("cargo_cult_submissions_total", "Submissions and updates sent to Airtable"),
+    gallery_fetches_total: Counter::new("cargo_cult_gallery_fetches_total", "Times the gallery was loaded from Airtable"),
+    airtable_requests_total: Counter::new("cargo_cult_airtable_requests_total", "Requests made to the Airtable API"),
+    airtable_errors_total: Counter::new("cargo_cult_airtable_errors_total", "Airtable requests that failed or got an error status"),
+    airtable_request_duration: Histogram::new("cargo_cult_airtable_request_duration_seconds", "Airtable API latency", LATENCY_BUCKETS)
+});
+
+const SESSION_BUCKETS: &[f64] = &[10.0, 30.0, 60.0, 300.0, 600.0, 1800.0, 3600.0];
+const LATENCY_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+pub struct Counter {
+    name: &'static str,
+    help: &'static str,
+    value: AtomicU64
+}
+
+impl Counter {
+    fn new(name: &'static str, help: &'static str) -> Self {
+        Self { name, help, value: AtomicU64::new(0) }
+    }
+
+    pub fn inc(&self) {
+        self.value.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String) {
+        let _ = write!(out, "# HELP {0} {1}\n# TYPE {0} counter\n{0} {2}\n", self.name, self.help, self.value.load(Ordering::Relaxed));
+    }
+}
+
+pub struct Gauge {
+    name: &'static str,
+    help: &'static str,
+    value: AtomicI64
+}
+
+impl Gauge {
+    fn new(name: &'static str, help: &'static str) -> Self {
+        Self { name, help, value: AtomicI64::new(0) }
+    }
+
+    pub fn inc(&self) {
+        self.value.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dec(&self) {
+        self.value.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Counts one more until the returned guard is dropped, so the gauge comes back down however
+    /// the thing it's counting ends (panics and aborted tasks included).
+    pub fn track(&'static self) -> GaugeGuard {
+        self.inc();
+        GaugeGuard(self)
+    }
+
+    fn render(&self, out: &mut String) {
+        let _ = write!(out, "# HELP {0} {1}\n# TYPE {0} gauge\n{0} {2}\n", self.name, self.help, self.value.load(Ordering::Relaxed));
+    }
+}
+
+pub struct GaugeGuard(&'static Gauge);
+
+impl Drop for GaugeGuard {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+pub struct Histogram {
+    name: &'static str,
+    help: &'static str,
+    bounds: &'static [f64],
+    state: Mutex<HistogramState>
+}
+
+struct HistogramState {
+    // one per bound, not cumulative
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64
+}
+
+impl Histogram {
+    fn new(name: &'static str, help: &'static str, bounds: &'static [f64]) -> Self {
+        let state = HistogramState { counts: vec![0; bounds.len()], sum: 0.0, count: 0 };
+        Self { name, help, bounds, state: Mutex::new(state) }
+    }
+
+    pub fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(bucket) = self.bounds.iter().position(|&bound| seconds <= bound) {
+            state.counts[bucket] += 1;
+        }
+        state.sum += seconds;
+        state.count += 1;
+    }
+
+    fn render(&self, out: &mut String) {
+        let state = self.state.lock().unwrap();
+
+        let _ = write!(out, "# HELP {0} {1}\n# TYPE {0} histogram\n", self.name, self.help);
+        let mut cumulative = 0;
+        for (bound, count) in self.bounds.iter().zip(&state.counts) {
+            cumulative += count;
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", self.name, bound, cumulative);
+        }
+        let _ = write!(out, "{0}_bucket{{le=\"+Inf\"}} {1}\n{0}_sum {2}\n{0}_count {1}\n", self.name, state.count, state.sum);
+    }
+}
+
+impl Metrics {
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        self.ssh_connections.render(&mut out);
+        self.ssh_connections_total.render(&mut out);
+        self.ssh_session_duration.render(&mut out);
+        self.sandbox_sessions.render(&mut out);
+        self.sandbox_session_duration.render(&mut out);
+        self.submissions_total.render(&mut out);
+        self.gallery_fetches_total.render(&mut out);
+        self.airtable_requests_total.render(&mut out);
+        self.airtable_errors_total.render(&mut out);
+        self.airtable_request_duration.render(&mut out);
+
+        out
+    }
+}
+
+/// Serves `GET /metrics` on `METRICS_ADDR` (e.g. `127.0.0.1:9100`), if it's set.
+pub async fn serve() {
+    let Ok(addr) = env::var("METRICS_ADDR") else { return };
+
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(error) => {
+            warn!(addr, %error, "couldn't start the metrics endpoint");
+            return;
+        }
+    };
+    info!(addr, "serving metrics");
+
+    loop {
+        let Ok((stream, _)) = listener.accept().await else { continue };
+        tokio::spawn(respond(stream));
+    }
+}
+
+// the request line fits in this easily, and anything after it is ignored
+const MAX_REQUEST_BYTES: usize = 4096;
+
+async fn respond(mut stream: TcpStream) {
+    let mut request = vec![0; MAX_REQUEST_BYTES];
+    let Ok(Ok(length)) = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut request)).await else { return };
+    let request = String::from_utf8_lossy(&request[..length]);
+
+    let (status, body) = match request.split_whitespace().take(2).collect::<Vec<&str>>()[..] {
+        ["GET", "/metrics"] => ("200 OK", METRICS.render()),
+        _ => ("404 Not Found", "Not found\n".to_string())
+    };
+
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counters_and_gauges_render_their_value() {
+        let counter = Counter::new("test_events_total", "Events seen");
+        counter.inc();
+        counter.inc();
+        let gauge = Gauge::new("test_open", "Things open");
+        gauge.inc();
+        gauge.inc();
+        gauge.dec();
+
+        let mut out = String::new();
+        counter.render(&mut out);
+        gauge.render(&mut out);
+
+        assert_eq!(out, "\
+# HELP test_events_total Events seen
+# TYPE test_events_total counter
+test_events_total 2
+# HELP test_open Things open
+# TYPE test_open gauge
+test_open 1
+");
+    }
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let histogram = Histogram::new("test_duration_seconds", "How long things took", &[0.5, 1.0, 5.0]);
+        for millis in [100, 500, 700, 3000, 60_000] {
+            histogram.observe(Duration::from_millis(millis));
+        }
+
+        let mut out = String::new();
+        histogram.render(&mut out);
+
+        // the last observation is past every bound, so only +Inf and the count include it
+        assert_eq!(out, "\
+# HELP test_duration_seconds How long things took
+# TYPE test_duration_seconds histogram
+test_duration_seconds_bucket{le=\"0.5\"} 2
+test_duration_seconds_bucket{le=\"1\"} 3
+test_duration_seconds_bucket{le=\"5\"} 4
+test_duration_seconds_bucket{le=\"+Inf\"} 5
+test_duration_seconds_sum 64.3
+test_duration_seconds_count 5
+");
+    }
+
+    #[test]
+    fn tracked_gauges_come_back_down() {
+        let gauge: &'static Gauge = Box::leak(Box::new(Gauge::new("test_tracked", "Things being tracked")));
+
+        let first = gauge.track();
+        let second = gauge.track();
+        assert_eq!(gauge.value.load(Ordering::Relaxed), 2);
+
+        drop(first);
+        assert_eq!(gauge.value.load(Ordering::Relaxed), 1);
+
+        // a session that panics still drops its guard
+        let _ = std::panic::catch_unwind(move || {
+            let _second = second;
+            panic!("the session crashed");
+        });
+        assert_eq!(gauge.value.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn every_metric_is_rendered() {
+        let out = METRICS.render();
+
+        for name in [
+            "cargo_cult_ssh_connections", "cargo_cult_ssh_connections_total", "cargo_cult_ssh_session_duration_seconds",
+            "cargo_cult_sandbox_sessions", "cargo_cult_sandbox_session_duration_seconds", "cargo_cult_submissions_total",
+            "cargo_cult_gallery_fetches_total", "cargo_cult_airtable_requests_total", "cargo_cult_airtable_errors_total",
+            "cargo_cult_airtable_request_duration_seconds"
+        ] {
+            assert!(out.contains(&format!("# HELP {name} ")), "{name} is missing:\n{out}");
+        }
+        assert_eq!(out.matches("le=\"+Inf\"").count(), 3);
+    }
+}
@@ -0,0 +1,153 @@
+use std::env;
+use std::fs::Permissions;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+#[cfg(not(test))]
+use std::sync::LazyLock;
+use std::time::Duration;
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::broadcast;
+use tokio::time::sleep;
+use tracing::{info, warn};
+
+/// A message for everyone connected, shown as a banner at the top of their terminal.
+#[derive(Clone, Debug)]
+pub struct Notice {
+    pub message: String
+}
+
+const NOTICE_BUFFER: usize = 16;
+
+#[cfg(not(test))]
+static NOTICES: LazyLock<broadcast::Sender<Notice>> = LazyLock::new(|| broadcast::channel(NOTICE_BUFFER).0);
+
+// every test runs its apps on a thread of its own, so a notice in one test doesn't end up on the
+// screens of the others
+#[cfg(test)]
+thread_local! {
+    static NOTICES: broadcast::Sender<Notice> = broadcast::channel(NOTICE_BUFFER).0;
+}
+
+fn with_notices<T>(f: impl FnOnce(&broadcast::Sender<Notice>) -> T) -> T {
+    #[cfg(not(test))]
+    return f(&NOTICES);
+    #[cfg(test)]
+    return NOTICES.with(f);
+}
+
+/// Shows `message` to every open session. Returns how many sessions it reached.
+pub fn announce(message: impl Into<String>) -> usize {
+    with_notices(|notices| notices.send(Notice { message: message.into() }).unwrap_or(0))
+}
+
+pub fn subscribe() -> broadcast::Receiver<Notice> {
+    with_notices(broadcast::Sender::subscribe)
+}
+
+const DEFAULT_ADMIN_SOCKET: &str = "cargo-cult.sock";
+// a notice has to fit on one line of someone's terminal anyway
+const MAX_MESSAGE_BYTES: u64 = 1024;
+// how long to wait after accepting a connection fails (say, out of file descriptors) before trying again
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+fn admin_socket() -> String {
+    env::var("CARGO_CULT_ADMIN_SOCKET").unwrap_or(DEFAULT_ADMIN_SOCKET.to_string())
+}
+
+/// Accepts messages from `cargo-cult broadcast` on a unix socket (`CARGO_CULT_ADMIN_SOCKET`,
+/// `cargo-cult.sock` by default) that only the server's user can connect to.
+pub async fn listen() {
+    let path = admin_socket();
+    // left behind if the last server didn't exit cleanly
+    let _ = fs::remove_file(&path).await;
+
+    let listener = match bind_privately(&path).await {
+        Ok(listener) => listener,
+        Err(error) => {
+            warn!(path, %error, "couldn't open the admin socket");
+            return;
+        }
+    };
+
+    loop {
+        let mut stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(error) => {
+                warn!(%error, "couldn't accept an admin connection");
+                sleep(ACCEPT_RETRY_DELAY).await;
+                continue;
+            }
+        };
+
+        tokio::spawn(async move {
+            let mut message = String::new();
+            if (&mut stream).take(MAX_MESSAGE_BYTES).read_to_string(&mut message).await.is_err() {
+                return;
+            }
+            let message = message.trim();
+            if message.is_empty() {
+                return;
+            }
+
+            let reached = announce(message);
+            info!(message, sessions = reached, "broadcast sent");
+            let _ = stream.write_all(format!("Sent to {reached} sessions.\n").as_bytes()).await;
+        });
+    }
+}
+
+/// Binds a socket at `path` that only this user can connect to. It's created in a directory
+/// nobody else can get into and only moved to `path` once its permissions are restricted, so
+/// there's no moment where anyone else could connect.
+async fn bind_privately(path: &str) -> std::io::Result<UnixListener> {
+    let staging = format!("{path}.tmp");
+    let _ = fs::remove_dir_all(&staging).await;
+    fs::DirBuilder::new().mode(0o700).create(&staging).await?;
+
+    let staged = Path::new(&staging).join("socket");
+    let bound = async {
+        let listener = UnixListener::bind(&staged)?;
+        fs::set_permissions(&staged, Permissions::from_mode(0o600)).await?;
+        fs::rename(&staged, path).await?;
+        Ok(listener)
+    }.await;
+
+    fs::remove_dir_all(&staging).await?;
+    bound
+}
+
+/// Sends `message` to a running server's admin socket, returning its reply.
+pub async fn send(message: &str) -> std::io::Result<String> {
+    let mut stream = UnixStream::connect(admin_socket()).await?;
+    stream.write_all(message.as_bytes()).await?;
+    stream.shutdown().await?;
+
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply).await?;
+    Ok(reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+    use super::*;
+
+    #[tokio::test]
+    async fn admin_socket_is_only_reachable_by_its_owner() {
+        let path = env::temp_dir().join(format!("cargo-cult-test-{}.sock", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let listener = bind_privately(path).await.unwrap();
+        let metadata = fs::metadata(path).await.unwrap();
+        assert!(metadata.file_type().is_socket());
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        assert!(!Path::new(&format!("{path}.tmp")).exists());
+
+        let (connected, accepted) = tokio::join!(UnixStream::connect(path), listener.accept());
+        assert!(connected.is_ok() && accepted.is_ok());
+
+        fs::remove_file(path).await.unwrap();
+    }
+}
@@ -0,0 +1,147 @@
+use std::env;
+use std::io::ErrorKind;
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+
+const KEY_VAR: &str = "CARGO_CULT_PII_KEY";
+const SEALED_PREFIX: &str = "enc:v1:";
+const NONCE_LEN: usize = 12;
+
+/// Encrypts personal details (email and postal address) before they're written to local storage.
+///
+/// The key is 32 random bytes, base64-encoded, in `CARGO_CULT_PII_KEY`
+/// (e.g. from `openssl rand -base64 32`).
+#[derive(Clone)]
+pub struct PiiCipher {
+    cipher: Aes256Gcm
+}
+
+impl PiiCipher {
+    /// The cipher for the configured key, or `None` if there isn't one. A key that's set but
+    /// malformed is an error, rather than a reason to quietly stop storing anything.
+    pub fn from_env() -> std::io::Result<Option<Self>> {
+        let Ok(key) = env::var(KEY_VAR) else {
+            return Ok(None);
+        };
+        let key = STANDARD.decode(key.trim()).ok()
+            .filter(|key| key.len() == 32)
+            .ok_or(std::io::Error::new(ErrorKind::InvalidInput, "CARGO_CULT_PII_KEY isn't 32 base64-encoded bytes"))?;
+
+        Ok(Some(Self { cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)) }))
+    }
+
+    /// A cipher with a random key, for tests.
+    #[cfg(test)]
+    pub fn random() -> Self {
+        Self { cipher: Aes256Gcm::new(&Aes256Gcm::generate_key(&mut OsRng)) }
+    }
+
+    pub fn encrypt(&self, plaintext: &str) -> std::io::Result<String> {
+        // optional fields stay blank, there's nothing to hide in them
+        if plaintext.is_empty() {
+            return Ok(String::new());
+        }
+
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self.cipher.encrypt(&nonce, plaintext.as_bytes())
+            .map_err(|_| std::io::Error::other("encrypting PII failed"))?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+
+        Ok(format!("{SEALED_PREFIX}{}", STANDARD.encode(sealed)))
+    }
+
+    pub fn decrypt(&self, sealed: &str) -> std::io::Result<String> {
+        if sealed.is_empty() {
+            return Ok(String::new());
+        }
+
+        let invalid = |message: &str| std::io::Error::new(ErrorKind::InvalidData, message.to_string());
+
+        let sealed = sealed.strip_prefix(SEALED_PREFIX).ok_or(invalid("PII field isn't encrypted"))?;
+        let sealed = STANDARD.decode(sealed).map_err(|_| invalid("PII field isn't valid base64"))?;
+        if sealed.len() < NONCE_LEN {
+            return Err(invalid("PII field is truncated"));
+        }
+
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let plaintext = self.cipher.decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| invalid("decrypting PII failed (wrong CARGO_CULT_PII_KEY?)"))?;
+
+        String::from_utf8(plaintext).map_err(|_| invalid("PII field isn't valid UTF-8"))
+    }
+}
+
+/// What to show in place of a personal detail in logs and `Debug` output.
+pub fn mask(value: &str) -> &'static str {
+    if value.is_empty() { "" } else { "<redacted>" }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+    use base64::Engine;
+    use base64::engine::general_purpose::STANDARD;
+    use super::{PiiCipher, SEALED_PREFIX};
+
+    #[test]
+    fn sealed_values_open_again() {
+        let pii = PiiCipher::random();
+
+        let sealed = pii.encrypt("ferris@example.com").unwrap();
+        assert!(sealed.starts_with("enc:v1:"));
+        assert!(!sealed.contains("ferris"));
+        assert_eq!(pii.decrypt(&sealed).unwrap(), "ferris@example.com");
+
+        // a fresh nonce every time, so equal values don't look equal on disk
+        assert_ne!(pii.encrypt("ferris@example.com").unwrap(), sealed);
+    }
+
+    #[test]
+    fn blank_values_stay_blank() {
+        let pii = PiiCipher::random();
+
+        assert_eq!(pii.encrypt("").unwrap(), "");
+        assert_eq!(pii.decrypt("").unwrap(), "");
+    }
+
+    #[test]
+    fn tampered_values_are_refused() {
+        let pii = PiiCipher::random();
+        let sealed = pii.encrypt("1 Crab Lane").unwrap();
+
+        let mut bytes = STANDARD.decode(sealed.strip_prefix(SEALED_PREFIX).unwrap()).unwrap();
+        *bytes.last_mut().unwrap() ^= 1;
+        let tampered = format!("{SEALED_PREFIX}{}", STANDARD.encode(bytes));
+
+        assert_eq!(pii.decrypt(&tampered).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn other_keys_cant_open_values() {
+        let sealed = PiiCipher::random().encrypt("ferris@example.com").unwrap();
+
+        let error = PiiCipher::random().decrypt(&sealed).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(error.to_string().contains("wrong CARGO_CULT_PII_KEY"));
+    }
+
+    #[test]
+    fn only_sealed_values_are_opened() {
+        let pii = PiiCipher::random();
+        let sealed = pii.encrypt("ferris@example.com").unwrap();
+
+        for (value, error) in [
+            ("ferris@example.com", "PII field isn't encrypted"),
+            (sealed.strip_prefix(SEALED_PREFIX).unwrap(), "PII field isn't encrypted"),
+            ("enc:v2:AAAA", "PII field isn't encrypted"),
+            ("enc:v1:not base64!", "PII field isn't valid base64"),
+            ("enc:v1:AAAA", "PII field is truncated")
+        ] {
+            assert_eq!(pii.decrypt(value).unwrap_err().to_string(), error, "{value}");
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use std::env;
+use std::fs::{create_dir_all, File};
+use std::io::Write;
+use std::path::Path;
+use std::str;
+use std::time::Instant;
+use chrono::Utc;
+use serde_json::json;
+use crate::TerminalParams;
+
+/// Tees terminal output into an [asciicast v2](https://docs.asciinema.org/manual/asciicast/v2/)
+/// file, so sessions can be replayed with `asciinema play`. Recording is off unless
+/// `CARGO_CULT_RECORDINGS_DIR` is set.
+pub struct Recording {
+    file: File,
+    start: Instant,
+    // the start of a UTF-8 character split across two writes
+    pending: Vec<u8>
+}
+
+impl Recording {
+    /// Starts a recording named after the user and `label`, if recordings are enabled.
+    pub fn start(label: &str, params: &TerminalParams) -> std::io::Result<Option<Self>> {
+        let Ok(dir) = env::var("CARGO_CULT_RECORDINGS_DIR") else {
+            return Ok(None);
+        };
+
+        Self::start_in(dir, label, params).map(Some)
+    }
+
+    /// Starts a recording in `dir`, whatever `CARGO_CULT_RECORDINGS_DIR` says.
+    pub fn start_in(dir: impl AsRef<Path>, label: &str, params: &TerminalParams) -> std::io::Result<Self> {
+        create_dir_all(&dir)?;
+
+        let now = Utc::now();
+        let name = format!("{}-{}-{}.cast", now.format("%Y%m%dT%H%M%S%.3f"), Self::sanitize(&params.username), Self::sanitize(label));
+        let mut file = File::create(dir.as_ref().join(name))?;
+
+        let header = json!({
+            "version": 2,
+            "width": params.col_width,
+            "height": params.row_height,
+            "timestamp": now.timestamp(),
+            "title": format!("{} ({})", label, params.username),
+            "env": { "TERM": params.term }
+        });
+        writeln!(file, "{}", header)?;
+
+        Ok(Self { file, start: Instant::now(), pending: Vec::new() })
+    }
+
+    pub fn output(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.pending.extend_from_slice(data);
+
+        // events are JSON strings, so hold back an incomplete character until the rest arrives
+        let complete = match str::from_utf8(&self.pending) {
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            _ => self.pending.len()
+        };
+        let text = String::from_utf8_lossy(&self.pending[..complete]).into_owned();
+        self.pending.drain(..complete);
+
+        if text.is_empty() {
+            return Ok(());
+        }
+
+        writeln!(self.file, "{}", json!([self.start.elapsed().as_secs_f64(), "o", text]))
+    }
+
+    fn sanitize(text: &str) -> String {
+        text.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' }).collect()
+    }
+}
@@ -0,0 +1,105 @@
+use clap::{Parser, Subcommand};
+
+// a part of the app someone can go straight to, either as a CLI command (`cargo-cult gallery`)
+// or through their SSH username (`ssh gallery@cargo-cult.example`). not a doc comment, since
+// clap would show it as the description of the whole CLI
+#[derive(Debug, Clone, PartialEq, Subcommand)]
+pub enum Route {
+    Menu,
+    Gallery,
+    Submit,
+
+    /// Runs a project from the gallery in a sandbox
+    Try {
+        #[arg(index = 1)]
+        package_name: String
+    },
+    /// Watches or joins someone's live session
+    Watch {
+        #[arg(index = 1)]
+        code: String
+    },
+    #[command(hide = true)]
+    Readme {
+        #[arg(index = 1)]
+        package_name: String
+    }
+}
+
+#[derive(Parser)]
+#[command(no_binary_name = true)]
+struct UsernameRoute {
+    #[command(subcommand)]
+    route: Route
+}
+
+impl Route {
+    /// Reads a route from an SSH username, with `+` between the route and its argument, like
+    /// `try+hc-cargo-cult`. Anything that isn't a route, such as an ordinary username, goes to
+    /// the menu.
+    ///
+    /// Everyone going to a route shares its username, so nothing private to a user (like their
+    /// drafts) can be keyed on the username.
+    pub fn from_username(username: &str) -> Self {
+        // links shared before routes existed look like `[crate-name]`
+        if let Some(package_name) = username.strip_prefix('[').and_then(|name| name.strip_suffix(']')) {
+            return match package_name {
+                "" => Route::Menu,
+                package_name => Route::Try { package_name: package_name.to_string() }
+            };
+        }
+
+        let mut parts = username.split('+');
+        let name = parts.next().unwrap_or_default().to_lowercase();
+        // nothing ever starts with a dash, so a username can't pass flags like --help
+        if parts.clone().any(|part| part.is_empty() || part.starts_with('-')) {
+            return Route::Menu;
+        }
+
+        UsernameRoute::try_parse_from([name.as_str()].into_iter().chain(parts))
+            .map(|parsed| parsed.route)
+            .unwrap_or(Route::Menu)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn try_route(package_name: &str) -> Route {
+        Route::Try { package_name: package_name.to_string() }
+    }
+
+    #[test]
+    fn routes_without_arguments() {
+        assert_eq!(Route::from_username("menu"), Route::Menu);
+        assert_eq!(Route::from_username("gallery"), Route::Gallery);
+        assert_eq!(Route::from_username("submit"), Route::Submit);
+        assert_eq!(Route::from_username("Gallery"), Route::Gallery);
+    }
+
+    #[test]
+    fn routes_with_arguments() {
+        assert_eq!(Route::from_username("try+hc-cargo-cult"), try_route("hc-cargo-cult"));
+        assert_eq!(Route::from_username("readme+hc-cargo-cult"), Route::Readme { package_name: "hc-cargo-cult".to_string() });
+        assert_eq!(Route::from_username("watch+ABC123"), Route::Watch { code: "ABC123".to_string() });
+    }
+
+    #[test]
+    fn legacy_crate_links() {
+        assert_eq!(Route::from_username("[hc-cargo-cult]"), try_route("hc-cargo-cult"));
+        assert_eq!(Route::from_username("[]"), Route::Menu);
+    }
+
+    #[test]
+    fn unknown_routes_go_to_the_menu() {
+        let usernames = [
+            "", "fiona", "root", "gallery+extra", "try", "try+", "try+a+b", "watch+",
+            "try+--help", "help", "--help", "-h", "+gallery", "[unclosed", "readme+-v"
+        ];
+
+        for username in usernames {
+            assert_eq!(Route::from_username(username), Route::Menu, "{username:?}");
+        }
+    }
+}
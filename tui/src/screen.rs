@@ -0,0 +1,555 @@
+use std::env;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::mem;
+use std::path::Path;
+use std::str;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// A virtual terminal for tests. It understands the escape codes the app writes (the ones
+/// crossterm produces, plus a few written by hand) well enough to show what a user would see,
+/// down to the colors.
+pub struct Screen {
+    cols: usize,
+    rows: usize,
+    cells: Vec<Vec<Cell>>,
+    // what newly printed characters look like
+    style: Style,
+
+    row: usize,
+    col: usize,
+    saved_cursor: (usize, usize),
+    // the last column has been written to, so the next character goes on a new line
+    wrap_pending: bool,
+    autowrap: bool,
+    scroll_region: (usize, usize),
+    title: String,
+
+    state: ParseState,
+    // the start of a UTF-8 character split across two writes
+    pending: Vec<u8>
+}
+
+enum ParseState {
+    Ground,
+    Escape,
+    // `ESC (` and friends, which are followed by one more character
+    Charset,
+    Csi(String),
+    Osc(String),
+    OscEscape(String)
+}
+
+#[derive(Clone, Copy)]
+struct Cell {
+    // the right half of a wide character is `WIDE_CONTINUATION`
+    c: char,
+    style: Style
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct Style {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    pub bold: bool,
+    pub dim: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub blink: bool,
+    pub reverse: bool
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    Ansi(u8),
+    Rgb(u8, u8, u8)
+}
+
+const WIDE_CONTINUATION: char = '\0';
+
+// named the way crossterm names them, e.g. `on_dark_red()`
+const COLOR_NAMES: [&str; 16] = [
+    "black", "dark_red", "dark_green", "dark_yellow", "dark_blue", "dark_magenta", "dark_cyan", "grey",
+    "dark_grey", "red", "green", "yellow", "blue", "magenta", "cyan", "white"
+];
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self { c: ' ', style: Style::default() }
+    }
+}
+
+impl Screen {
+    pub fn new(cols: usize, rows: usize) -> Self {
+        Self {
+            cols,
+            rows,
+            cells: vec![vec![Cell::default(); cols]; rows],
+            style: Style::default(),
+            row: 0,
+            col: 0,
+            saved_cursor: (0, 0),
+            wrap_pending: false,
+            autowrap: true,
+            scroll_region: (0, rows - 1),
+            title: String::new(),
+            state: ParseState::Ground,
+            pending: Vec::new()
+        }
+    }
+
+    pub fn feed(&mut self, data: &[u8]) {
+        self.pending.extend_from_slice(data);
+
+        let mut input = mem::take(&mut self.pending);
+        let text = loop {
+            match str::from_utf8(&input) {
+                Ok(text) => break text.to_string(),
+                // an incomplete character at the end waits for the rest
+                Err(e) if e.error_len().is_none() => {
+                    self.pending = input.split_off(e.valid_up_to());
+                    break String::from_utf8(input).unwrap();
+                }
+                Err(e) => {
+                    let end = e.valid_up_to() + e.error_len().unwrap();
+                    input.splice(e.valid_up_to()..end, char::REPLACEMENT_CHARACTER.to_string().into_bytes());
+                }
+            }
+        };
+
+        for c in text.chars() {
+            self.advance(c);
+        }
+    }
+
+    pub fn line(&self, row: usize) -> String {
+        self.cells[row].iter().map(|cell| cell.c).filter(|&c| c != WIDE_CONTINUATION).collect::<String>().trim_end().to_string()
+    }
+
+    pub fn contains(&self, text: &str) -> bool {
+        (0..self.rows).any(|row| self.line(row).contains(text))
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    /// The text, the cursor, and every run of styled cells, in a form that's easy to review
+    /// in a diff.
+    pub fn snapshot(&self) -> String {
+        let mut snapshot = format!("{self}\ncursor: row {}, col {}\n", self.row, self.col);
+
+        for (row, cells) in self.cells.iter().enumerate() {
+            let mut start = 0;
+            for col in 1..=self.cols {
+                if col < self.cols && cells[col].style == cells[start].style {
+                    continue;
+                }
+                if cells[start].style != Style::default() {
+                    snapshot += &format!("row {row}, cols {start}-{}: {}\n", col - 1, cells[start].style);
+                }
+                start = col;
+            }
+        }
+
+        snapshot
+    }
+
+    /// Compares the screen with `src/snapshots/<name>.snap`. Run the tests with
+    /// `UPDATE_SNAPSHOTS=1` to write the snapshots again after an intended change.
+    pub fn assert_snapshot(&self, name: &str) {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/snapshots").join(format!("{name}.snap"));
+        let actual = self.snapshot();
+
+        if env::var_os("UPDATE_SNAPSHOTS").is_some() {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, &actual).unwrap();
+            return;
+        }
+
+        let Ok(expected) = fs::read_to_string(&path) else {
+            panic!("There's no snapshot at {}, run with UPDATE_SNAPSHOTS=1 to write it. The screen was:\n{actual}", path.display());
+        };
+        assert!(expected == actual, "{name} doesn't match its snapshot.\nexpected:\n{expected}\nactual:\n{actual}");
+    }
+
+    fn advance(&mut self, c: char) {
+        self.state = match mem::replace(&mut self.state, ParseState::Ground) {
+            ParseState::Ground => match c {
+                '\x1b' => ParseState::Escape,
+                '\r' => {
+                    self.move_to(self.row, 0);
+                    ParseState::Ground
+                }
+                '\n' => {
+                    self.wrap_pending = false;
+                    self.line_feed();
+                    ParseState::Ground
+                }
+                '\x08' => {
+                    self.move_to(self.row, self.col.saturating_sub(1));
+                    ParseState::Ground
+                }
+                '\t' => {
+                    self.move_to(self.row, (self.col / 8 + 1) * 8);
+                    ParseState::Ground
+                }
+                c if c.is_control() => ParseState::Ground,
+                c => {
+                    self.print(c);
+                    ParseState::Ground
+                }
+            },
+            ParseState::Escape => match c {
+                '[' => ParseState::Csi(String::new()),
+                ']' => ParseState::Osc(String::new()),
+                '(' | ')' => ParseState::Charset,
+                '7' => {
+                    self.saved_cursor = (self.row, self.col);
+                    ParseState::Ground
+                }
+                '8' => {
+                    let (row, col) = self.saved_cursor;
+                    self.move_to(row, col);
+                    ParseState::Ground
+                }
+                _ => ParseState::Ground
+            },
+            ParseState::Charset => ParseState::Ground,
+            ParseState::Csi(mut params) => match c {
+                '\x40'..='\x7e' => {
+                    self.csi(&params, c);
+                    ParseState::Ground
+                }
+                c => {
+                    params.push(c);
+                    ParseState::Csi(params)
+                }
+            },
+            ParseState::Osc(mut text) => match c {
+                '\x07' => {
+                    self.osc(&text);
+                    ParseState::Ground
+                }
+                '\x1b' => ParseState::OscEscape(text),
+                c => {
+                    text.push(c);
+                    ParseState::Osc(text)
+                }
+            },
+            // anything after the ESC ends the string, though it should always be `\`
+            ParseState::OscEscape(text) => {
+                self.osc(&text);
+                ParseState::Ground
+            }
+        };
+    }
+
+    fn print(&mut self, c: char) {
+        let width = c.width().unwrap_or(0);
+        if width == 0 {
+            return;
+        }
+
+        if self.wrap_pending || (width == 2 && self.col + 1 >= self.cols && self.autowrap) {
+            self.wrap_pending = false;
+            self.col = 0;
+            self.line_feed();
+        }
+
+        let col = self.col.min(self.cols - width);
+        self.cells[self.row][col] = Cell { c, style: self.style };
+        if width == 2 {
+            self.cells[self.row][col + 1] = Cell { c: WIDE_CONTINUATION, style: self.style };
+        }
+
+        if col + width >= self.cols {
+            self.col = self.cols - 1;
+            self.wrap_pending = self.autowrap;
+        } else {
+            self.col = col + width;
+        }
+    }
+
+    fn line_feed(&mut self) {
+        let (top, bottom) = self.scroll_region;
+
+        if self.row == bottom {
+            self.cells.remove(top);
+            self.cells.insert(bottom, vec![Cell::default(); self.cols]);
+        } else if self.row + 1 < self.rows {
+            self.row += 1;
+        }
+    }
+
+    fn move_to(&mut self, row: usize, col: usize) {
+        self.row = row.min(self.rows - 1);
+        self.col = col.min(self.cols - 1);
+        self.wrap_pending = false;
+    }
+
+    fn csi(&mut self, params: &str, command: char) {
+        let private = params.starts_with('?');
+        let numbers: Vec<usize> = params.trim_start_matches('?').split(';').map(|n| n.parse().unwrap_or(0)).collect();
+        // most commands treat a missing or zero argument as 1
+        let arg = |index: usize| numbers.get(index).copied().filter(|&n| n > 0).unwrap_or(1);
+        let mode = numbers.first().copied().unwrap_or(0);
+
+        match command {
+            'A' => self.move_to(self.row.saturating_sub(arg(0)), self.col),
+            'B' => self.move_to(self.row + arg(0), self.col),
+            'C' => self.move_to(self.row, self.col + arg(0)),
+            'D' => self.move_to(self.row, self.col.saturating_sub(arg(0))),
+            'E' => self.move_to(self.row + arg(0), 0),
+            'F' => self.move_to(self.row.saturating_sub(arg(0)), 0),
+            'G' => self.move_to(self.row, arg(0) - 1),
+            'd' => self.move_to(arg(0) - 1, self.col),
+            'H' | 'f' => self.move_to(arg(0) - 1, arg(1) - 1),
+            'J' => match mode {
+                0 => self.clear(self.row, self.col, self.rows - 1, self.cols),
+                1 => self.clear(0, 0, self.row, self.col + 1),
+                _ => self.clear(0, 0, self.rows - 1, self.cols)
+            },
+            'K' => match mode {
+                0 => self.clear(self.row, self.col, self.row, self.cols),
+                1 => self.clear(self.row, 0, self.row, self.col + 1),
+                _ => self.clear(self.row, 0, self.row, self.cols)
+            },
+            'r' => {
+                let top = arg(0) - 1;
+                let bottom = numbers.get(1).copied().filter(|&n| n > 0).unwrap_or(self.rows) - 1;
+                self.scroll_region = if top < bottom && bottom < self.rows { (top, bottom) } else { (0, self.rows - 1) };
+                self.move_to(0, 0);
+            }
+            's' => self.saved_cursor = (self.row, self.col),
+            'u' => {
+                let (row, col) = self.saved_cursor;
+                self.move_to(row, col);
+            }
+            'h' | 'l' if private && mode == 7 => self.autowrap = command == 'h',
+            'm' => self.select_graphic_rendition(&numbers),
+            // bracketed paste, cursor visibility and the like don't change what's on screen
+            _ => {}
+        }
+    }
+
+    fn select_graphic_rendition(&mut self, numbers: &[usize]) {
+        let mut numbers = numbers.iter().copied();
+
+        while let Some(number) = numbers.next() {
+            match number {
+                0 => self.style = Style::default(),
+                1 => self.style.bold = true,
+                2 => self.style.dim = true,
+                3 => self.style.italic = true,
+                4 => self.style.underline = true,
+                5 | 6 => self.style.blink = true,
+                7 => self.style.reverse = true,
+                22 => {
+                    self.style.bold = false;
+                    self.style.dim = false;
+                }
+                23 => self.style.italic = false,
+                24 => self.style.underline = false,
+                25 => self.style.blink = false,
+                27 => self.style.reverse = false,
+                30..=37 => self.style.fg = Some(Color::Ansi(number as u8 - 30)),
+                90..=97 => self.style.fg = Some(Color::Ansi(number as u8 - 90 + 8)),
+                40..=47 => self.style.bg = Some(Color::Ansi(number as u8 - 40)),
+                100..=107 => self.style.bg = Some(Color::Ansi(number as u8 - 100 + 8)),
+                38 => self.style.fg = Self::extended_color(&mut numbers),
+                48 => self.style.bg = Self::extended_color(&mut numbers),
+                39 => self.style.fg = None,
+                49 => self.style.bg = None,
+                _ => {}
+            }
+        }
+    }
+
+    /// The rest of a `38;5;n` or `38;2;r;g;b` color.
+    fn extended_color(numbers: &mut impl Iterator<Item = usize>) -> Option<Color> {
+        match numbers.next()? {
+            5 => Some(Color::Ansi(numbers.next()? as u8)),
+            2 => Some(Color::Rgb(numbers.next()? as u8, numbers.next()? as u8, numbers.next()? as u8)),
+            _ => None
+        }
+    }
+
+    fn osc(&mut self, text: &str) {
+        if let Some(title) = text.strip_prefix("0;").or(text.strip_prefix("2;")) {
+            self.title = title.to_string();
+        }
+    }
+
+    /// Blanks everything from (`start_row`, `start_col`) up to, but not including,
+    /// (`end_row`, `end_col`), going row by row.
+    fn clear(&mut self, start_row: usize, start_col: usize, end_row: usize, end_col: usize) {
+        for row in start_row..=end_row {
+            let from = if row == start_row { start_col } else { 0 };
+            let to = if row == end_row { end_col } else { self.cols };
+            for cell in &mut self.cells[row][from.min(self.cols)..to.min(self.cols)] {
+                *cell = Cell::default();
+            }
+        }
+        self.wrap_pending = false;
+    }
+}
+
+impl Display for Screen {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let border = "-".repeat(self.cols);
+        writeln!(f, "+{border}+")?;
+        for row in 0..self.rows {
+            let line = self.line(row);
+            writeln!(f, "|{line}{}|", " ".repeat(self.cols.saturating_sub(line.width())))?;
+        }
+        write!(f, "+{border}+")
+    }
+}
+
+impl Display for Style {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut parts = Vec::new();
+
+        if let Some(fg) = self.fg {
+            parts.push(fg.to_string());
+        }
+        if let Some(bg) = self.bg {
+            parts.push(format!("on {bg}"));
+        }
+        for (enabled, name) in [
+            (self.bold, "bold"),
+            (self.dim, "dim"),
+            (self.italic, "italic"),
+            (self.underline, "underline"),
+            (self.blink, "blink"),
+            (self.reverse, "reverse")
+        ] {
+            if enabled {
+                parts.push(name.to_string());
+            }
+        }
+
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+impl Display for Color {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Color::Ansi(index) if (index as usize) < COLOR_NAMES.len() => write!(f, "{}", COLOR_NAMES[index as usize]),
+            Color::Ansi(index) => write!(f, "color {index}"),
+            Color::Rgb(r, g, b) => write!(f, "#{r:02x}{g:02x}{b:02x}")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Color, Screen, Style};
+
+    fn screen(cols: usize, rows: usize, input: &str) -> Screen {
+        let mut screen = Screen::new(cols, rows);
+        screen.feed(input.as_bytes());
+        screen
+    }
+
+    fn lines(screen: &Screen) -> Vec<String> {
+        (0..screen.rows).map(|row| screen.line(row)).collect()
+    }
+
+    #[test]
+    fn wraps_at_the_last_column() {
+        let screen = screen(4, 3, "abcdef");
+        assert_eq!(lines(&screen), ["abcd", "ef", ""]);
+        assert_eq!((screen.row, screen.col), (1, 2));
+
+        // a line exactly as wide as the screen doesn't leave a blank line behind it
+        let screen = self::screen(4, 3, "abcd\r\nef");
+        assert_eq!(lines(&screen), ["abcd", "ef", ""]);
+    }
+
+    #[test]
+    fn overwrites_the_last_column_without_autowrap() {
+        let screen = screen(4, 2, "\x1b[?7labcdef");
+        assert_eq!(lines(&screen), ["abcf", ""]);
+    }
+
+    #[test]
+    fn moves_the_cursor_and_clears() {
+        let mut screen = screen(5, 3, "abcde\r\nfghij\r\nklmno");
+
+        screen.feed(b"\x1b[2;3H\x1b[K");
+        assert_eq!(lines(&screen), ["abcde", "fg", "klmno"]);
+
+        screen.feed(b"\x1b[A\x1b[2DX\x1b[B\x1b[CY\x1b[4GZ");
+        assert_eq!(lines(&screen), ["Xbcde", "fgYZ", "klmno"]);
+
+        screen.feed(b"\x1b[3;2H\x1b[1K");
+        assert_eq!(lines(&screen), ["Xbcde", "fgYZ", "  mno"]);
+
+        screen.feed(b"\x1b[1;4H\x1b[J");
+        assert_eq!(lines(&screen), ["Xbc", "", ""]);
+    }
+
+    #[test]
+    fn scrolls_at_the_bottom() {
+        let screen = screen(3, 3, "a\r\nb\r\nc\r\nd");
+        assert_eq!(lines(&screen), ["b", "c", "d"]);
+        assert_eq!((screen.row, screen.col), (2, 1));
+    }
+
+    #[test]
+    fn scrolls_only_inside_the_scroll_region() {
+        let screen = screen(3, 3, "\x1b[1;2r\x1b[3;1Hz\x1b[1;1Ha\r\nb\r\nc");
+        assert_eq!(lines(&screen), ["b", "c", "z"]);
+    }
+
+    #[test]
+    fn tracks_styles() {
+        let screen = screen(8, 1, "\x1b[1;31mab\x1b[0m \x1b[38;5;208;48;2;1;2;3;4mc\x1b[22;24;39md\x1b[mef");
+
+        assert!(screen.cells[0][0].style == Style { fg: Some(Color::Ansi(1)), bold: true, ..Style::default() });
+        assert!(screen.cells[0][4].style == Style { bg: Some(Color::Rgb(1, 2, 3)), ..Style::default() });
+        assert_eq!(
+            screen.snapshot().lines().skip(4).collect::<Vec<_>>(),
+            [
+                "row 0, cols 0-1: dark_red bold",
+                "row 0, cols 3-3: color 208 on #010203 underline",
+                "row 0, cols 4-4: on #010203"
+            ]
+        );
+    }
+
+    #[test]
+    fn wide_characters_take_two_columns() {
+        let screen = screen(4, 2, "a張b");
+        assert_eq!(lines(&screen), ["a張b", ""]);
+
+        // one that doesn't fit at the end of a line moves to the next
+        let screen = self::screen(4, 2, "abc張");
+        assert_eq!(lines(&screen), ["abc", "張"]);
+        assert_eq!((screen.row, screen.col), (1, 2));
+    }
+
+    #[test]
+    fn characters_can_be_split_between_writes() {
+        let mut screen = Screen::new(4, 1);
+        let bytes = "é".as_bytes();
+
+        screen.feed(&bytes[..1]);
+        assert_eq!(screen.line(0), "");
+        screen.feed(&bytes[1..]);
+        assert_eq!(screen.line(0), "é");
+    }
+
+    #[test]
+    fn sets_the_title() {
+        let mut screen = screen(4, 1, "\x1b]0;hello\x07");
+        assert_eq!(screen.title(), "hello");
+
+        screen.feed(b"\x1b]2;bye\x1b\\x");
+        assert_eq!(screen.title(), "bye");
+        assert_eq!(screen.line(0), "x");
+    }
+}
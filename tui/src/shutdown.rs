@@ -0,0 +1,96 @@
+use std::env;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::time::{sleep, Instant};
+use tracing::info;
+use crate::limits::minutes;
+use crate::notices::announce;
+
+static SUBMISSIONS_IN_PROGRESS: AtomicUsize = AtomicUsize::new(0);
+
+const DEFAULT_DRAIN_SECS: u64 = 120;
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Held while someone is filling in a submission, so shutting down can wait for them.
+pub struct Submitting(());
+
+pub fn submitting() -> Submitting {
+    SUBMISSIONS_IN_PROGRESS.fetch_add(1, Ordering::Relaxed);
+    Submitting(())
+}
+
+impl Drop for Submitting {
+    fn drop(&mut self) {
+        SUBMISSIONS_IN_PROGRESS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Warns everyone that the server is going down, then waits until nobody is mid-submission or
+/// `CARGO_CULT_DRAIN_SECS` (default 2 minutes) have passed.
+pub async fn drain() {
+    let timeout = Duration::from_secs(env::var("CARGO_CULT_DRAIN_SECS").ok().and_then(|secs| secs.parse().ok()).unwrap_or(DEFAULT_DRAIN_SECS));
+    let deadline = Instant::now() + timeout;
+
+    let reached = announce(format!(
+        "The server is restarting in {}. Please finish what you're doing, your drafts are saved.",
+        minutes(timeout)
+    ));
+    info!(sessions = reached, timeout_secs = timeout.as_secs(), "draining");
+
+    wait_until_done(&SUBMISSIONS_IN_PROGRESS, deadline).await;
+}
+
+async fn wait_until_done(submissions: &AtomicUsize, deadline: Instant) {
+    loop {
+        let in_progress = submissions.load(Ordering::Relaxed);
+        if in_progress == 0 {
+            info!("no submissions in progress");
+            return;
+        }
+        if Instant::now() >= deadline {
+            info!(in_progress, "gave up waiting for submissions");
+            return;
+        }
+
+        sleep(POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn draining_returns_at_once_without_submissions() {
+        let start = Instant::now();
+        wait_until_done(&AtomicUsize::new(0), start + Duration::from_secs(120)).await;
+
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn draining_waits_for_the_last_submission() {
+        let submissions = Arc::new(AtomicUsize::new(2));
+        let start = Instant::now();
+
+        let finishing = submissions.clone();
+        tokio::spawn(async move {
+            sleep(Duration::from_secs(10)).await;
+            finishing.fetch_sub(1, Ordering::Relaxed);
+            sleep(Duration::from_secs(20)).await;
+            finishing.fetch_sub(1, Ordering::Relaxed);
+        });
+        wait_until_done(&submissions, start + Duration::from_secs(120)).await;
+
+        assert!(start.elapsed() >= Duration::from_secs(30) && start.elapsed() <= Duration::from_secs(30) + POLL_INTERVAL);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn draining_gives_up_at_the_deadline() {
+        let start = Instant::now();
+        wait_until_done(&AtomicUsize::new(1), start + Duration::from_secs(120)).await;
+
+        assert!(start.elapsed() >= Duration::from_secs(120) && start.elapsed() <= Duration::from_secs(120) + POLL_INTERVAL);
+    }
+}
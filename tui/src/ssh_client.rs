@@ -5,12 +5,24 @@ use std::path::Path;
 use tokio::net::ToSocketAddrs;
 use std::error::Error;
 use std::io::Write;
-use std::ops::Deref;
 use russh_keys::load_secret_key;
 use std::time::Duration;
 use std::sync::Arc;
 use std::str;
 use crate::{SharedTerminalParams, TerminalCode, TerminalParams};
+use crate::app::{AsyncWriter, OutputSink};
+use tokio::time::{interval, MissedTickBehavior};
+use std::cmp::min;
+use crate::live::{LiveSession, Participant};
+use crate::recording::Recording;
+use tracing::warn;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+// where sandboxes are started, with `docker run`
+pub const SANDBOX_HOST: &str = "localhost:2222";
+pub const SANDBOX_USER: &str = "cargo-cult";
+pub const SANDBOX_KEY: &str = "id_ed25519";
 
 struct ForwardingClient();
 
@@ -24,53 +36,110 @@ impl client::Handler for ForwardingClient {
     } 
 }
 
-pub struct SSHForwardingSession<'a, Out: Write> {
+async fn connect<P: AsRef<Path>, A: ToSocketAddrs>(
+    key_path: P,
+    user: impl Into<String>,
+    addrs: A
+) -> Result<client::Handle<ForwardingClient>, Box<dyn Error>> {
+    let key_pair = load_secret_key(key_path, None)?;
+
+    let config = client::Config {
+        inactivity_timeout: Some(Duration::from_secs(60*30)),
+        ..<_>::default()
+    };
+
+    let config = Arc::new(config);
+    let sh = ForwardingClient {};
+
+    let mut session = client::connect(config, addrs, sh).await?;
+
+    let auth_res = session
+        .authenticate_publickey(user, Arc::new(key_pair))
+        .await?;
+
+    if !auth_res {
+        return Err(Box::from("Auth w/ publickey failed"))
+    }
+
+    Ok(session)
+}
+
+/// Runs `command` without a terminal and returns its exit status and what it wrote to stdout,
+/// for when there's no user to show it to.
+pub async fn output<P: AsRef<Path>, A: ToSocketAddrs>(
+    key_path: P,
+    user: impl Into<String>,
+    addrs: A,
+    command: &str
+) -> Result<(u32, Vec<u8>), Box<dyn Error>> {
+    let session = connect(key_path, user, addrs).await?;
+    let mut channel = session.channel_open_session().await?;
+    channel.exec(true, command).await?;
+
+    let mut stdout = Vec::new();
+    let mut code = None;
+    while let Some(msg) = channel.wait().await {
+        match msg {
+            ChannelMsg::Data { ref data } => stdout.extend_from_slice(data),
+            ChannelMsg::ExitStatus { exit_status } => code = Some(exit_status),
+            _ => {}
+        }
+    }
+
+    match code {
+        Some(code) => Ok((code, stdout)),
+        None => Err(Box::from("The command didn't exit"))
+    }
+}
+
+/// Quotes `arg` for the sandbox host's shell.
+pub fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+pub struct SSHForwardingSession<'a, Out: OutputSink> {
     session: client::Handle<ForwardingClient>,
 
     params: SharedTerminalParams,
 
     input: &'a mut Receiver<TerminalCode>,
-    output: &'a mut Out
+    output: &'a mut AsyncWriter<Out>,
+
+    recording: Option<Recording>,
+    live: Option<Arc<LiveSession>>
 }
 
-impl<'a, Out: Write> SSHForwardingSession<'a, Out> {
+impl<'a, Out: OutputSink> SSHForwardingSession<'a, Out> {
     pub async fn connect<P: AsRef<Path>, A: ToSocketAddrs>(
         key_path: P,
         user: impl Into<String>,
         addrs: A,
         params: SharedTerminalParams,
         input: &'a mut Receiver<TerminalCode>,
-        output: &'a mut Out 
+        output: &'a mut AsyncWriter<Out>
     ) -> Result<SSHForwardingSession<'a, Out>, Box<dyn Error>> {
-        let key_pair = load_secret_key(key_path, None)?;
-
-        let config = client::Config {
-            inactivity_timeout: Some(Duration::from_secs(60*30)),
-            ..<_>::default()
-        };
-
-        let config = Arc::new(config);
-        let sh = ForwardingClient {};
-
-        let mut session = client::connect(config, addrs, sh).await?;
+        let session = connect(key_path, user, addrs).await?;
 
-        let auth_res = session
-            .authenticate_publickey(user, Arc::new(key_pair))
-            .await?;
+        Ok(Self { session, params, input, output, recording: None, live: None })
+    }
 
-        if !auth_res {
-            return Err(Box::from("Auth w/ publickey failed"))
-        }
+    /// Tees the output of every following [`Self::call`] into `recording`.
+    pub fn record(&mut self, recording: Recording) {
+        self.recording = Some(recording);
+    }
 
-        Ok(Self { session, params, input, output})
+    /// Broadcasts the output of every following [`Self::call`] to `live`'s viewers, and keeps
+    /// a status line with who's watching at the bottom of the host's terminal.
+    pub fn share(&mut self, live: Arc<LiveSession>) {
+        self.live = Some(live);
     }
 
     pub async fn call(&mut self, command: &str) -> Result<u32, Box<dyn Error>> {
         let mut channel = self.session.channel_open_session().await?;
 
-        let params = self.params.lock().await;
+        let params = self.params.lock().await.clone();
         // todo: handle terminal resize (on ssh server side?)
-        let &TerminalParams {row_height, col_width, ref modes, ref term, username: _} = params.deref();
+        let TerminalParams {row_height, col_width, ref modes, ref term, ..} = params;
 
         channel
             .request_pty(
@@ -87,6 +156,12 @@ impl<'a, Out: Write> SSHForwardingSession<'a, Out> {
 
         let code;
 
+        let mut viewer_check = interval(Duration::from_secs(1));
+        viewer_check.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+        let mut guest_input = self.live.as_ref().and_then(|live| live.take_guest_input());
+        let mut shared_layout = None;
+
         loop {
             // Handle one of the possible events:
             tokio::select! {
@@ -94,14 +169,15 @@ impl<'a, Out: Write> SSHForwardingSession<'a, Out> {
                 Some(r) = self.input.recv() => {
                     channel.data(r.raw_bytes.as_slice()).await?
                 },
+                // A guest typed something into the shared session
+                Some(input) = async { guest_input.as_mut()?.recv().await } => {
+                    channel.data(input.as_slice()).await?
+                },
                 // There's an event available on the session channel
                 Some(msg) = channel.wait() => {
                     match msg {
                         // Write data to the terminal
-                        ChannelMsg::Data { ref data } => {
-                            self.output.write_all(data)?;
-                            self.output.flush()?;
-                        }
+                        ChannelMsg::Data { ref data } => self.emit(data).await?,
                         // The command has returned an exit code
                         ChannelMsg::ExitStatus { exit_status } => {
                             code = exit_status;
@@ -111,9 +187,162 @@ impl<'a, Out: Write> SSHForwardingSession<'a, Out> {
                         _ => {}
                     }
                 },
+                // Let the host know when people start or stop watching, and fit the session to
+                // everyone driving it
+                _ = viewer_check.tick(), if self.live.is_some() => {
+                    let live = self.live.clone().unwrap();
+                    let guests = live.guests();
+                    let viewers = live.viewers();
+                    let layout = SharedLayout::new(&params, &guests, viewers);
+                    if shared_layout.as_ref() != Some(&layout) {
+                        channel.window_change(layout.col_width, layout.row_height, 0, 0).await?;
+                        self.emit(layout.scroll_region(shared_layout.as_ref()).as_bytes()).await?;
+                        shared_layout = Some(layout);
+                    }
+                    if shared_layout.as_ref().is_some_and(|layout| layout.shared) {
+                        // redrawn every time in case the program cleared the screen
+                        self.draw(shared_layout.as_ref().unwrap().status_line(&live.host, &guests, viewers).as_bytes())?;
+                    }
+                },
             }
         }
 
+        if shared_layout.as_ref().is_some_and(|layout| layout.shared) {
+            self.emit(SharedLayout::new(&params, &[], 0).scroll_region(shared_layout.as_ref()).as_bytes()).await?;
+        }
+
         Ok(code)
     }
+
+    /// Writes output from the session to the user's terminal, the recording and any viewers.
+    /// Waits for the user's terminal to keep up, so a slow client slows the session down.
+    async fn emit(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.output.write_all(data)?;
+        self.output.flush()?;
+
+        if let Some(Err(e)) = self.recording.as_mut().map(|recording| recording.output(data)) {
+            warn!(error = %e, "stopping recording");
+            self.recording = None;
+        }
+        if let Some(live) = &self.live {
+            live.broadcast(data);
+        }
+
+        self.output.ready().await
+    }
+
+    /// Writes to the host's terminal and everyone following the session, but not the recording
+    /// or the scrollback, for things that are redrawn every second.
+    fn draw(&mut self, data: &[u8]) -> std::io::Result<()> {
+        if let Some(live) = &self.live {
+            live.broadcast_transient(data);
+        }
+
+        self.output.write_all(data)?;
+        self.output.flush()
+    }
+}
+
+/// The pty size of a session with anyone watching: the smallest terminal of everyone driving
+/// it, minus a row at the bottom for the status line.
+#[derive(PartialEq)]
+struct SharedLayout {
+    col_width: u32,
+    row_height: u32,
+    shared: bool
+}
+
+impl SharedLayout {
+    fn new(host: &TerminalParams, guests: &[Participant], viewers: usize) -> Self {
+        if guests.is_empty() && viewers == 0 {
+            return Self { col_width: host.col_width, row_height: host.row_height, shared: false };
+        }
+
+        let col_width = guests.iter().map(|guest| guest.col_width).fold(host.col_width, min);
+        let row_height = guests.iter().map(|guest| guest.row_height).fold(host.row_height, min);
+
+        Self { col_width, row_height: row_height.saturating_sub(1).max(1), shared: true }
+    }
+
+    /// Keeps scrolling out of the status line's row (or gives it back once everyone else leaves).
+    fn scroll_region(&self, previous: Option<&SharedLayout>) -> String {
+        match previous {
+            _ if self.shared => format!("\x1b7\x1b[1;{}r\x1b8", self.row_height),
+            Some(previous) if previous.shared => format!("\x1b7\x1b[r\x1b[{};1H\x1b[2K\x1b8", previous.row_height + 1),
+            _ => String::new()
+        }
+    }
+
+    /// Who's in the session and how many are watching, for everyone in it. It leaves out the
+    /// invite code, which only the host was shown, once, when the session started.
+    fn status_line(&self, host: &str, guests: &[Participant], viewers: usize) -> String {
+        let names: Vec<&str> = guests.iter().map(|guest| guest.name.as_str()).collect();
+        let status = match names.is_empty() {
+            true => format!(" {} | {} watching", host, viewers),
+            false => format!(" {} + {} | {} watching | guests leave with Ctrl-]", host, names.join(", "), viewers)
+        };
+
+        // usernames can be anything, so they're cut to fit in columns, not characters, and
+        // can't sneak in escape codes
+        let width = self.col_width as usize;
+        let mut used = 0;
+        let status: String = status.graphemes(true)
+            .filter(|grapheme| !grapheme.chars().any(char::is_control))
+            .take_while(|grapheme| {
+                used += grapheme.width();
+                used <= width
+            })
+            .collect();
+        let padding = " ".repeat(width - status.width());
+
+        // save the cursor, draw on the row below the session, then put the cursor back
+        format!("\x1b7\x1b[{};1H\x1b[2K\x1b[7m{status}{padding}\x1b8", self.row_height + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::live::LiveSession;
+    use crate::TerminalParams;
+    use super::SharedLayout;
+
+    #[test]
+    fn status_lines_fit_the_terminal() {
+        let live = LiveSession::start("ホスト");
+        let _guests = [live.join("ゲスト", 20, 10), live.join("evil\x1b[2J", 20, 10)];
+        let layout = SharedLayout { col_width: 15, row_height: 9, shared: true };
+
+        // the Japanese characters take two columns each, so the last one doesn't fit
+        let line = layout.status_line(&live.host, &live.guests(), 3);
+        assert_eq!(line, "\x1b7\x1b[10;1H\x1b[2K\x1b[7m ホスト + ゲス \x1b8");
+
+        let layout = SharedLayout { col_width: 80, ..layout };
+        let line = layout.status_line(&live.host, &live.guests(), 3);
+        assert!(line.contains(" ホスト + ゲスト, evil[2J | 3 watching | guests leave with Ctrl-]     "));
+    }
+
+    #[test]
+    fn watched_sessions_keep_a_row_for_the_status_line() {
+        let live = LiveSession::start("host");
+        let host = TerminalParams {
+            term: String::new(),
+            col_width: 80,
+            row_height: 24,
+            modes: Vec::new(),
+            username: "host".to_string(),
+            key_fingerprint: None,
+            peer_ip: None
+        };
+
+        let alone = SharedLayout::new(&host, &[], 0);
+        assert!(!alone.shared);
+        assert_eq!((alone.col_width, alone.row_height), (80, 24));
+
+        let watched = SharedLayout::new(&host, &[], 2);
+        assert!(watched.shared);
+        assert_eq!((watched.col_width, watched.row_height), (80, 23));
+
+        let line = watched.status_line(&live.host, &[], 2);
+        assert_eq!(line, format!("\x1b7\x1b[24;1H\x1b[2K\x1b[7m host | 2 watching{}\x1b8", " ".repeat(62)));
+    }
 }
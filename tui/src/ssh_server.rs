@@ -2,19 +2,48 @@ use async_trait::async_trait;
 use russh::{Channel, ChannelId, Pty, server};
 use std::str;
 use russh::server::{Auth, Msg, Session, Server as _, Handle};
+use russh::MethodSet;
+use russh_keys::key::PublicKey;
 use tokio::sync::{mpsc, Mutex};
-use std::sync::Arc;
-use std::io::{Write};
-use std::time::Duration;
-use futures::executor::block_on;
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
 use russh::Error::SendError;
 use tokio::fs::File;
 use tokio::io::AsyncReadExt;
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::mpsc::{Sender};
 use tokio::task::{AbortHandle};
-use crate::{SharedTerminalParams, TerminalCode, TerminalParams};
-use crate::app::App;
-use crate::terminal::channel_data_to_terminal_codes;
+use crate::{open_local_store, SharedTerminalParams, TerminalCode, TerminalParams};
+use crate::app::{App, OutputSink};
+use crate::database::{SubmissionStore, SubmissionsAirtableBase};
+use crate::exec;
+use crate::limits::{LimitReached, Permit, LIMITS};
+use crate::metrics::{self, METRICS};
+use crate::notices;
+use crate::route::Route;
+use crate::shutdown;
+use crate::storage::LocalStore;
+use crate::terminal::TerminalDecoder;
+use tracing::{error, field, info, info_span, warn, Instrument, Span};
+
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(1);
+
+// chunks of a command's stdin waiting to be read, before the client has to wait
+const STDIN_BUFFER: usize = 16;
+
+/// Every terminal with an app running in it, by session id and channel, so they can be closed on
+/// shutdown.
+static OPEN_TERMINALS: LazyLock<std::sync::Mutex<HashMap<(u64, ChannelId), OpenTerminal>>> = LazyLock::new(Default::default);
+
+#[derive(Clone)]
+struct OpenTerminal {
+    handle: Handle,
+    channel: ChannelId,
+    app: AbortHandle
+}
 
 pub async fn ssh_server() {
     let mut key = String::new();
@@ -30,69 +59,165 @@ pub async fn ssh_server() {
         ..Default::default()
     };
     let config = Arc::new(config);
-    let mut sh = Server::new();
+    let store: Arc<dyn SubmissionStore> = Arc::new(SubmissionsAirtableBase::new());
+    let local = Arc::new(open_local_store());
+    let mut sh = Server::new(Span::none(), 0, store, local);
+
+    tokio::spawn(metrics::serve());
+    tokio::spawn(notices::listen());
+
+    info!("listening on port 22");
+    tokio::select! {
+        result = sh.run_on_address(config, ("0.0.0.0", 22)) => result.unwrap(),
+        // dropping the server stops it accepting connections, while the open ones carry on
+        _ = shutdown_signal() => {
+            info!("shutting down");
+            shutdown::drain().await;
+            close_open_terminals().await;
+        }
+    }
+}
+
+async fn shutdown_signal() {
+    let mut terminate = signal(SignalKind::terminate()).expect("listening for SIGTERM to work");
 
-    sh.run_on_address(config, ("0.0.0.0", 22)).await.unwrap();
+    tokio::select! {
+        _ = terminate.recv() => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+}
+
+async fn close_open_terminals() {
+    let terminals: Vec<OpenTerminal> = OPEN_TERMINALS.lock().unwrap().values().cloned().collect();
+    info!(count = terminals.len(), "closing open terminals");
+
+    for terminal in terminals {
+        terminal.app.abort();
+
+        let goodbye = "\x1b[0m\r\n\r\n  The server is restarting. See you soon!\r\n";
+        let _ = terminal.handle.data(terminal.channel, goodbye.as_bytes().to_vec().into()).await;
+        let _ = terminal.handle.eof(terminal.channel).await;
+        let _ = terminal.handle.close(terminal.channel).await;
+    }
 }
 
 struct TerminalHandle {
     handle: Handle,
-    channel_id: ChannelId,
-    
-    sink: Vec<u8>
+    channel_id: ChannelId
 }
 
 impl TerminalHandle {
     fn new(handle: Handle, channel_id: ChannelId) -> Self {
-        Self {
-            handle, channel_id,
-            sink: Vec::new()
-        }
+        Self { handle, channel_id }
     }
 }
 
-impl Write for TerminalHandle {
-    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        let original_length = buf.len();
+#[async_trait]
+impl OutputSink for TerminalHandle {
+    async fn send(&mut self, data: Vec<u8>) -> std::io::Result<()> {
+        self.handle.data(self.channel_id, data.into()).await
+            .map_err(|_| std::io::Error::other("channel closed"))
+    }
+}
 
-        self.sink.extend_from_slice(buf);
 
-        Ok(original_length)
-    }
+struct Server {
+    // a client can open several sessions over one connection, each with its own app
+    channels: HashMap<ChannelId, ChannelState>,
+    // and run commands without a terminal, e.g. `ssh <host> list`
+    commands: HashMap<ChannelId, CommandState>,
+    // terminals that have been asked for, waiting to see if it's for a shell or a command
+    ptys: HashMap<ChannelId, TerminalParams>,
 
-    fn flush(&mut self) -> std::io::Result<()> {
-        let data = self.sink.clone().into();
-        block_on(self.handle.data(self.channel_id, data)).unwrap();
-        self.sink.clear();
-        
-        Ok(())
+    username: Option<String>,
+    key_fingerprint: Option<String>,
+
+    // everything logged for this connection, including by its App, is inside this span
+    span: Span,
+    session_id: u64,
+    connected_at: Option<Instant>,
+
+    peer: Option<SocketAddr>,
+    // shared by every connection, so they all reuse the same HTTP client
+    store: Arc<dyn SubmissionStore>,
+    local: Arc<LocalStore>,
+    // refused connections still get a shell, to tell them to come back later
+    connection: Option<Result<Permit, LimitReached>>
+}
+
+/// An app running in one of a connection's channels.
+struct ChannelState {
+    sender: Sender<TerminalCode>,
+    handle: AbortHandle,
+    params: SharedTerminalParams,
+    decoder: TerminalDecoder
+}
+
+impl Drop for ChannelState {
+    fn drop(&mut self) {
+        self.handle.abort()
     }
 }
 
+/// A command running in one of a connection's channels.
+struct CommandState {
+    // closed once the client has sent everything, or the command doesn't want any more
+    stdin: Option<Sender<Vec<u8>>>,
+    handle: AbortHandle
+}
 
-struct Server {
-    sender: Option<Sender<TerminalCode>>,
-    handle: Option<AbortHandle>,
-    params: Option<SharedTerminalParams>,
-    
-    username: Option<String>
+impl Drop for CommandState {
+    fn drop(&mut self) {
+        self.handle.abort()
+    }
 }
 
 impl Server {
-    fn new() -> Self {
+    fn new(span: Span, session_id: u64, store: Arc<dyn SubmissionStore>, local: Arc<LocalStore>) -> Self {
         Self {
-            sender: None,
-            handle: None,
-            params: None,
-            username: None
+            channels: HashMap::new(),
+            commands: HashMap::new(),
+            ptys: HashMap::new(),
+            username: None,
+            key_fingerprint: None,
+            span,
+            session_id,
+            connected_at: None,
+            peer: None,
+            store,
+            local,
+            connection: None
         }
     }
+
+    fn set_username(&mut self, user: &str) {
+        self.span.record("username", user);
+        self.username = Some(user.to_string());
+    }
+
+    fn terminal_params(&self, term: &str, col_width: u32, row_height: u32, modes: &[(Pty, u32)]) -> TerminalParams {
+        TerminalParams {
+            term: String::from(term),
+            col_width,
+            row_height,
+            modes: Vec::from(modes),
+            username: self.username.clone().unwrap(),
+            key_fingerprint: self.key_fingerprint.clone(),
+            peer_ip: self.peer.map(|peer| peer.ip())
+        }
+    }
+
+    fn refused(&self) -> Option<LimitReached> {
+        self.connection.as_ref().and_then(|connection| connection.as_ref().err().cloned())
+    }
 }
 
 impl Drop for Server {
     fn drop(&mut self) {
-        if let Some(ref handle) = self.handle {
-            handle.abort()
+        if let Some(connected_at) = self.connected_at {
+            METRICS.ssh_connections.dec();
+            METRICS.ssh_session_duration.observe(connected_at.elapsed());
+            info!(parent: &self.span, "connection closed");
         }
     }
 }
@@ -100,12 +225,32 @@ impl Drop for Server {
 impl server::Server for Server {
     type Handler = Self;
 
-    fn new_client(&mut self, _: Option<std::net::SocketAddr>) -> Self {
-        Self::new()
+    fn new_client(&mut self, peer: Option<SocketAddr>) -> Self {
+        let session_id = NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed);
+        let span = info_span!(
+            "connection",
+            session_id,
+            peer = peer.map(|peer| peer.to_string()),
+            username = field::Empty
+        );
+        info!(parent: &span, "connection opened");
+        METRICS.ssh_connections.inc();
+        METRICS.ssh_connections_total.inc();
+
+        let connection = LIMITS.connect(peer.map(|peer| peer.ip()));
+        if let Err(ref limit) = connection {
+            warn!(parent: &span, reason = limit.reason, "connection refused");
+        }
+
+        let mut client = Self::new(span, session_id, self.store.clone(), self.local.clone());
+        client.connected_at = Some(Instant::now());
+        client.peer = peer;
+        client.connection = Some(connection);
+        client
     }
     
-    fn handle_session_error(&mut self, _error: <Self::Handler as server::Handler>::Error) {
-        eprintln!("Session error: {:#?}", _error);
+    fn handle_session_error(&mut self, error: <Self::Handler as server::Handler>::Error) {
+        error!(?error, "session error");
     }
 }
 
@@ -114,8 +259,29 @@ impl server::Handler for Server {
     type Error = russh::Error;
 
     async fn auth_none(&mut self, user: &str) -> Result<Auth, Self::Error> {
-        dbg!(user);
-        self.username = Some(user.to_string());
+        self.set_username(user);
+        // ask for a key so we can recognize returning users, but let anyone without one in
+        // through keyboard-interactive
+        Ok(Auth::Reject {
+            proceed_with_methods: Some(MethodSet::PUBLICKEY | MethodSet::KEYBOARD_INTERACTIVE)
+        })
+    }
+
+    async fn auth_publickey(&mut self, user: &str, public_key: &PublicKey) -> Result<Auth, Self::Error> {
+        self.set_username(user);
+        self.key_fingerprint = Some(format!("SHA256:{}", public_key.fingerprint()));
+        info!(parent: &self.span, key_fingerprint = self.key_fingerprint, "authenticated with a public key");
+        Ok(Auth::Accept)
+    }
+
+    async fn auth_keyboard_interactive(
+        &mut self,
+        user: &str,
+        _submethods: &str,
+        _response: Option<server::Response<'async_trait>>,
+    ) -> Result<Auth, Self::Error> {
+        self.set_username(user);
+        info!(parent: &self.span, "authenticated without a key");
         Ok(Auth::Accept)
     }
 
@@ -127,15 +293,77 @@ impl server::Handler for Server {
         Ok(true)
     }
 
+    async fn channel_close(
+        &mut self,
+        channel: ChannelId,
+        _session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        // dropping the state stops the channel's app or command
+        if self.channels.remove(&channel).is_some() {
+            info!(parent: &self.span, ?channel, "terminal closed");
+        }
+        self.commands.remove(&channel);
+        self.ptys.remove(&channel);
+
+        Ok(())
+    }
+
+    async fn channel_eof(
+        &mut self,
+        channel: ChannelId,
+        _session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        if let Some(command) = self.commands.get_mut(&channel) {
+            command.stdin = None;
+        }
+
+        Ok(())
+    }
+
+    async fn window_change_request(
+        &mut self,
+        channel: ChannelId,
+        col_width: u32,
+        row_height: u32,
+        _pix_width: u32,
+        _pix_height: u32,
+        _session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        if let Some(state) = self.channels.get(&channel) {
+            let mut params = state.params.lock().await;
+            params.col_width = col_width;
+            params.row_height = row_height;
+        }
+        if let Some(params) = self.ptys.get_mut(&channel) {
+            params.col_width = col_width;
+            params.row_height = row_height;
+        }
+
+        Ok(())
+    }
+
     async fn data(
         &mut self,
-        _channel: ChannelId,
+        channel: ChannelId,
         data: &[u8],
         _session: &mut Session,
     ) -> Result<(), Self::Error> {
+        if let Some(command) = self.commands.get_mut(&channel) {
+            if let Some(ref stdin) = command.stdin {
+                if stdin.send(Vec::from(data)).await.is_err() {
+                    command.stdin = None;
+                }
+            }
+            return Ok(());
+        }
+
+        let state = self.channels.get_mut(&channel).ok_or(SendError)?;
 
-        for code in channel_data_to_terminal_codes(data) {
-            self.sender.as_ref().ok_or(SendError)?.send(code).await.expect("sending to work")
+        for code in state.decoder.decode(data) {
+            // the app has already exited, and the channel is about to close
+            if state.sender.send(code).await.is_err() {
+                break;
+            }
         }
 
         Ok(())
@@ -149,60 +377,227 @@ impl server::Handler for Server {
                          _pix_width: u32,
                          _pix_height: u32,
                          modes: &[(Pty, u32)],
-                         session: &mut Session) -> Result<(), Self::Error> {
-        
-        let mut terminal_handle = TerminalHandle::new(session.handle(), channel);
-        terminal_handle.flush()?;
+                         _session: &mut Session) -> Result<(), Self::Error> {
+        // the app only starts with the shell, since the terminal might be for a command
+        let params = self.terminal_params(term, col_width, row_height, modes);
+        self.ptys.insert(channel, params);
+
+        Ok(())
+    }
+
+    async fn shell_request(&mut self, channel: ChannelId, session: &mut Session) -> Result<(), Self::Error> {
+        // there's nothing to show without a terminal to show it in
+        let Some(params) = self.ptys.remove(&channel) else {
+            return Ok(());
+        };
+        let (term, col_width, row_height) = (params.term.clone(), params.col_width, params.row_height);
+
+        let terminal_handle = TerminalHandle::new(session.handle(), channel);
+
+        let terminal_params = Arc::from(Mutex::from(params));
 
-        let terminal_params = Arc::from(Mutex::from(TerminalParams {
-            term: String::from(term),
-            col_width,
-            row_height,
-            modes: Vec::from(modes),
-            username: self.username.take().unwrap()
-        }));
-       
         let (tx, rx) = mpsc::channel(1);
 
         let handle = session.handle();
 
         let mut app = {
             let handle = handle.clone();
-            App::new(terminal_handle, rx, terminal_params.clone(), move || {
+            App::new(terminal_handle, rx, terminal_params.clone(), self.store.clone(), self.local.clone(), move || {
+                // the channel might already be closed, say if the client disconnected
                 tokio::spawn(async move {
-                    handle.eof(channel).await.unwrap();
-                    handle.close(channel).await.unwrap();
+                    let _ = handle.eof(channel).await;
+                    let _ = handle.close(channel).await;
                 });
             })
         };
-        
-        self.sender = Some(tx);
 
         {
-            let terminal_params = terminal_params.clone();
+            let params = terminal_params.clone();
             let handle = handle.clone();
-            
+
+            info!(parent: &self.span, ?channel, term, col_width, row_height, "terminal opened");
+
+            let refused = self.refused();
+
             let task = tokio::spawn(async move {
-                let username = terminal_params.clone().lock().await.username.clone();
-                if username.starts_with("[") && username.ends_with("]") {
-                    app.run_project(username[1..username.len() - 1].to_string()).await.unwrap();
+                let username = terminal_params.lock().await.username.clone();
+                if let Some(limit) = refused {
+                    app.refuse(limit).await;
                 } else {
-                    app.run().await.unwrap();
+                    // writes fail once the client has gone, which is how most sessions end
+                    match app.route(Route::from_username(&username)).await {
+                        Ok(()) => {}
+                        Err(e) => info!(error = %e, "terminal lost")
+                    }
                 }
+            }.instrument(self.span.clone()));
+
+            self.channels.insert(channel, ChannelState {
+                sender: tx,
+                handle: task.abort_handle(),
+                params,
+                decoder: TerminalDecoder::new()
             });
 
-            self.handle = Some(task.abort_handle());
+            let session_id = self.session_id;
+            OPEN_TERMINALS.lock().unwrap().insert((session_id, channel), OpenTerminal { handle: handle.clone(), channel, app: task.abort_handle() });
 
             tokio::spawn(async move {
                 let _ = task.await;
+                OPEN_TERMINALS.lock().unwrap().remove(&(session_id, channel));
 
                 let _ = handle.eof(channel).await;
                 let _ = handle.close(channel).await;
             });
         }
 
-        self.params = Some(terminal_params.clone());
+        Ok(())
+    }
+
+    async fn exec_request(
+        &mut self,
+        channel: ChannelId,
+        data: &[u8],
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        let command = String::from_utf8_lossy(data).into_owned();
+        info!(parent: &self.span, ?channel, command, "command received");
+        session.channel_success(channel);
+        // a terminal asked for first is only for the command, e.g. `ssh -t <host> list`
+        self.ptys.remove(&channel);
+
+        let params = self.terminal_params("", 0, 0, &[]);
+        let refused = self.refused();
+        let (stdin, stdin_receiver) = mpsc::channel(STDIN_BUFFER);
+        let store = self.store.clone();
+        let handle = session.handle();
+
+        let task = tokio::spawn(async move {
+            let output = match refused {
+                Some(limit) => exec::refuse(&limit),
+                None => exec::run(&command, &params, store.as_ref(), &exec::SandboxReadmes, stdin_receiver).await
+            };
+            info!(exit_status = output.exit_status, "command finished");
+
+            if !output.stdout.is_empty() {
+                let _ = handle.data(channel, output.stdout.into_bytes().into()).await;
+            }
+            if !output.stderr.is_empty() {
+                let _ = handle.extended_data(channel, 1, output.stderr.into_bytes().into()).await;
+            }
+            let _ = handle.exit_status_request(channel, output.exit_status).await;
+            let _ = handle.eof(channel).await;
+            let _ = handle.close(channel).await;
+        }.instrument(self.span.clone()));
+
+        self.commands.insert(channel, CommandState { stdin: Some(stdin), handle: task.abort_handle() });
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+    use russh::{client, server, ChannelMsg};
+    use russh_keys::key::{KeyPair, PublicKey};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::time::timeout;
+    use tracing::Span;
+    use crate::database::{FormData, MemoryStore};
+    use crate::pii::PiiCipher;
+    use crate::storage::LocalStore;
+    use super::Server;
+
+    struct TestClient;
+
+    #[async_trait]
+    impl client::Handler for TestClient {
+        type Error = russh::Error;
+
+        async fn check_server_key(&mut self, _key: &PublicKey) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+    }
+
+    /// Starts a server over an in-memory stream, with one approved project, and logs in to it.
+    async fn connect() -> client::Handle<TestClient> {
+        let store = MemoryStore::with(vec![FormData {
+            name: "Fiona".to_string(),
+            package_link: "https://crates.io/crates/ferris-says".to_string(),
+            package_name: Some("ferris-says".to_string()),
+            description: "Says things.".to_string(),
+            review_status: "Approved".to_string(),
+            ..FormData::new()
+        }]);
+        // nothing here saves drafts, so the directory is never made
+        let local = LocalStore::at(std::env::temp_dir().join("cargo-cult-ssh-server-test"), PiiCipher::random());
+        let server = Server::new(Span::none(), 0, Arc::new(store), Arc::new(local));
+
+        let config = server::Config {
+            keys: vec![KeyPair::generate_ed25519().unwrap()],
+            ..Default::default()
+        };
+        let (client_stream, server_stream) = tokio::io::duplex(64 * 1024);
+        // it reads the client's greeting before returning, so it can't be awaited here
+        tokio::spawn(async move {
+            server::run_stream(Arc::new(config), server_stream, server).await?.await
+        });
+
+        let mut session = client::connect_stream(Arc::new(client::Config::default()), client_stream, TestClient).await.unwrap();
+        let key = Arc::new(KeyPair::generate_ed25519().unwrap());
+        assert!(session.authenticate_publickey("someone", key).await.unwrap());
+        session
+    }
+
+    #[tokio::test]
+    async fn commands_run_alone_in_a_terminal() {
+        let session = connect().await;
+        let mut channel = session.channel_open_session().await.unwrap();
+
+        // like `ssh -t <host> submit --json`, which waits for the submission on stdin
+        channel.request_pty(false, "xterm", 80, 24, 0, 0, &[]).await.unwrap();
+        channel.exec(true, "submit --json").await.unwrap();
+        // long enough for a menu, if one had started too, to draw itself
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        channel.data(&b"{}"[..]).await.unwrap();
+        channel.eof().await.unwrap();
+
+        let mut stdout = Vec::new();
+        let mut stderr = Vec::new();
+        let mut exit_status = None;
+        while let Some(message) = timeout(Duration::from_secs(10), channel.wait()).await.unwrap() {
+            match message {
+                ChannelMsg::Data { data } => stdout.extend_from_slice(&data),
+                ChannelMsg::ExtendedData { data, .. } => stderr.extend_from_slice(&data),
+                ChannelMsg::ExitStatus { exit_status: status } => exit_status = Some(status),
+                ChannelMsg::Close => break,
+                _ => {}
+            }
+        }
+
+        // the menu's output would have been mixed in with the command's
+        assert_eq!(String::from_utf8_lossy(&stdout), "");
+        assert!(String::from_utf8_lossy(&stderr).starts_with("The submission is missing: name"));
+        assert_eq!(exit_status, Some(1));
+    }
+
+    #[tokio::test]
+    async fn shells_start_the_app() {
+        let session = connect().await;
+        let mut channel = session.channel_open_session().await.unwrap();
+
+        channel.request_pty(false, "xterm", 80, 24, 0, 0, &[]).await.unwrap();
+        channel.request_shell(false).await.unwrap();
+
+        let mut stdout = Vec::new();
+        while !String::from_utf8_lossy(&stdout).contains("Welcome to the Cargo Cult!") {
+            match timeout(Duration::from_secs(10), channel.wait()).await.unwrap() {
+                Some(ChannelMsg::Data { data }) => stdout.extend_from_slice(&data),
+                Some(_) => {}
+                None => panic!("the channel closed without showing the menu")
+            }
+        }
+    }
+}
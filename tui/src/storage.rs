@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt::{Debug, Formatter};
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use tokio::fs;
+use tokio::sync::Mutex;
+use crate::database::FormData;
+use crate::pii::{mask, PiiCipher};
+
+/// Contact details remembered for an SSH key, used to pre-fill the submission form.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Identity {
+    pub name: String,
+    pub slack_handle: String,
+    pub email: String
+}
+
+impl Debug for Identity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Identity")
+            .field("name", &self.name)
+            .field("slack_handle", &self.slack_handle)
+            .field("email", &mask(&self.email))
+            .finish()
+    }
+}
+
+/// Small JSON-file store on the server's disk, for state that doesn't belong in Airtable.
+///
+/// Emails and addresses are encrypted with [`PiiCipher`] before they're written; without a
+/// configured key, anything containing them is refused rather than stored in plaintext.
+pub struct LocalStore {
+    dir: PathBuf,
+    pii: Option<PiiCipher>
+}
+
+const DEFAULT_DATA_DIR: &str = "data";
+const IDENTITIES_FILE: &str = "identities.json";
+const DRAFTS_FILE: &str = "drafts.json";
+
+// every session shares the same files, so read-modify-write cycles are serialized
+static WRITE_LOCK: Mutex<()> = Mutex::const_new(());
+
+impl LocalStore {
+    pub fn new() -> std::io::Result<Self> {
+        let dir = env::var("CARGO_CULT_DATA_DIR").unwrap_or(DEFAULT_DATA_DIR.to_string());
+        Ok(Self { dir: PathBuf::from(dir), pii: PiiCipher::from_env()? })
+    }
+
+    /// A store in `dir`, whatever the environment says.
+    #[cfg(test)]
+    pub fn at(dir: PathBuf, pii: PiiCipher) -> Self {
+        Self { dir, pii: Some(pii) }
+    }
+
+    pub async fn identity(&self, key_fingerprint: &str) -> std::io::Result<Option<Identity>> {
+        let mut identities: HashMap<String, Identity> = self.read(IDENTITIES_FILE).await?;
+        identities.remove(key_fingerprint).map(|identity| self.unseal_identity(identity)).transpose()
+    }
+
+    pub async fn save_identity(&self, key_fingerprint: &str, identity: Identity) -> std::io::Result<()> {
+        let identity = Identity { email: self.pii()?.encrypt(&identity.email)?, ..identity };
+
+        let _guard = WRITE_LOCK.lock().await;
+
+        let mut identities: HashMap<String, Identity> = self.read(IDENTITIES_FILE).await?;
+        identities.insert(key_fingerprint.to_string(), identity);
+        self.write(IDENTITIES_FILE, &identities).await
+    }
+
+    pub async fn draft(&self, owner: &str) -> std::io::Result<Option<FormData>> {
+        let mut drafts: HashMap<String, FormData> = self.read(DRAFTS_FILE).await?;
+        drafts.remove(owner).map(|draft| self.unseal_form(&draft)).transpose()
+    }
+
+    pub async fn save_draft(&self, owner: &str, draft: &FormData) -> std::io::Result<()> {
+        let pii = self.pii()?;
+        let draft = draft.map_pii(|value| pii.encrypt(value))?;
+
+        let _guard = WRITE_LOCK.lock().await;
+
+        let mut drafts: HashMap<String, FormData> = self.read(DRAFTS_FILE).await?;
+        drafts.insert(owner.to_string(), draft);
+        self.write(DRAFTS_FILE, &drafts).await
+    }
+
+    pub async fn delete_draft(&self, owner: &str) -> std::io::Result<()> {
+        let _guard = WRITE_LOCK.lock().await;
+
+        let mut drafts: HashMap<String, FormData> = self.read(DRAFTS_FILE).await?;
+        if drafts.remove(owner).is_some() {
+            self.write(DRAFTS_FILE, &drafts).await?;
+        }
+        Ok(())
+    }
+
+    /// Every stored draft and identity, decrypted. Only for the `reveal-pii` admin command.
+    pub async fn reveal_all(&self) -> std::io::Result<(HashMap<String, FormData>, HashMap<String, Identity>)> {
+        let drafts: HashMap<String, FormData> = self.read(DRAFTS_FILE).await?;
+        let identities: HashMap<String, Identity> = self.read(IDENTITIES_FILE).await?;
+
+        let drafts = drafts.into_iter()
+            .map(|(owner, draft)| Ok((owner, self.unseal_form(&draft)?)))
+            .collect::<std::io::Result<_>>()?;
+        let identities = identities.into_iter()
+            .map(|(key_fingerprint, identity)| Ok((key_fingerprint, self.unseal_identity(identity)?)))
+            .collect::<std::io::Result<_>>()?;
+
+        Ok((drafts, identities))
+    }
+
+    fn pii(&self) -> std::io::Result<&PiiCipher> {
+        self.pii.as_ref().ok_or(std::io::Error::other("CARGO_CULT_PII_KEY isn't set, refusing to store personal details"))
+    }
+
+    fn unseal_form(&self, data: &FormData) -> std::io::Result<FormData> {
+        let pii = self.pii()?;
+        data.map_pii(|value| pii.decrypt(value))
+    }
+
+    fn unseal_identity(&self, identity: Identity) -> std::io::Result<Identity> {
+        Ok(Identity { email: self.pii()?.decrypt(&identity.email)?, ..identity })
+    }
+
+    async fn read<T: DeserializeOwned + Default>(&self, file: &str) -> std::io::Result<T> {
+        match fs::read(self.dir.join(file)).await {
+            Ok(contents) => Ok(serde_json::from_slice(&contents)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(T::default()),
+            Err(e) => Err(e)
+        }
+    }
+
+    async fn write<T: Serialize>(&self, file: &str, value: &T) -> std::io::Result<()> {
+        fs::create_dir_all(&self.dir).await?;
+
+        // write then rename, so a crash mid-write can't leave a truncated file behind
+        let path = self.dir.join(file);
+        let temp_path = self.dir.join(format!("{file}.tmp"));
+        fs::write(&temp_path, serde_json::to_vec_pretty(value)?).await?;
+        fs::rename(temp_path, path).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_store(name: &str) -> (LocalStore, PathBuf) {
+        let dir = env::temp_dir().join(format!("cargo-cult-test-storage-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        (LocalStore::at(dir.clone(), PiiCipher::random()), dir)
+    }
+
+    #[tokio::test]
+    async fn drafts_are_encrypted_on_disk() {
+        let (store, dir) = scratch_store("drafts");
+        let mut draft = FormData::new();
+        draft.name = "Ferris".to_string();
+        draft.email = "ferris@example.com".to_string();
+        draft.address_line1 = "1 Crab Lane".to_string();
+        draft.city = "Rustville".to_string();
+
+        store.save_draft("ferris", &draft).await.unwrap();
+
+        let raw = std::fs::read_to_string(dir.join(DRAFTS_FILE)).unwrap();
+        assert!(raw.contains("Ferris"));
+        assert!(raw.contains("enc:v1:"));
+        for detail in ["ferris@example.com", "1 Crab Lane", "Rustville"] {
+            assert!(!raw.contains(detail), "{detail} was written in plaintext:\n{raw}");
+        }
+
+        let read = store.draft("ferris").await.unwrap().unwrap();
+        assert_eq!((read.email.as_str(), read.address_line1.as_str(), read.city.as_str()), ("ferris@example.com", "1 Crab Lane", "Rustville"));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn identities_are_encrypted_on_disk() {
+        let (store, dir) = scratch_store("identities");
+        let identity = Identity { name: "Ferris".to_string(), slack_handle: "ferris".to_string(), email: "ferris@example.com".to_string() };
+
+        store.save_identity("SHA256:ferris", identity).await.unwrap();
+
+        let raw = std::fs::read_to_string(dir.join(IDENTITIES_FILE)).unwrap();
+        assert!(!raw.contains("ferris@example.com"), "the email was written in plaintext:\n{raw}");
+        assert_eq!(store.identity("SHA256:ferris").await.unwrap().unwrap().email, "ferris@example.com");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn stores_without_a_key_refuse_personal_details() {
+        let (_, dir) = scratch_store("no-key");
+        let store = LocalStore { dir: dir.clone(), pii: None };
+        let identity = Identity { name: "Ferris".to_string(), slack_handle: "ferris".to_string(), email: "ferris@example.com".to_string() };
+
+        assert!(store.save_identity("SHA256:ferris", identity).await.is_err());
+        assert!(store.save_draft("ferris", &FormData::new()).await.is_err());
+        assert!(!dir.exists());
+    }
+}
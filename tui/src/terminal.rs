@@ -1,23 +1,35 @@
-use std::io::{Stdout, stdout};
+use std::cmp::min;
 use std::process::exit;
+use std::str;
 use std::sync::Arc;
-use tokio::io::{AsyncReadExt, stdin};
+use async_trait::async_trait;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, stdin, stdout, Stdout};
 use tokio::sync::{mpsc, Mutex};
 use crossterm::terminal::{disable_raw_mode, enable_raw_mode, size};
 use tokio::sync::mpsc::Receiver;
-use crate::{AsciiCode, SharedTerminalParams, TerminalCode, TerminalParams};
-use crate::app::App;
-use crate::AsciiCode::{ArrowDown, ArrowUp, Backspace, Char, Enter, EoT};
+use crate::{AsciiCode, KeyModifiers, SharedTerminalParams, TerminalCode, TerminalParams};
+use crate::app::{App, OutputSink};
+use crate::database::SubmissionsAirtableBase;
+use crate::open_local_store;
+use crate::AsciiCode::*;
 
 pub async fn make_terminal_app() ->  App<Stdout, fn()> {
     let params: SharedTerminalParams = Arc::new(Mutex::new(get_terminal_params().unwrap()));
     let receiver = create_input_receiver().await;
-    App::new(stdout(), receiver, params, || {
+    App::new(stdout(), receiver, params, Arc::new(SubmissionsAirtableBase::new()), Arc::new(open_local_store()), || {
         disable_raw_mode().expect("TODO: panic message");
         exit(0)
     })
 }
 
+#[async_trait]
+impl OutputSink for Stdout {
+    async fn send(&mut self, data: Vec<u8>) -> std::io::Result<()> {
+        self.write_all(&data).await?;
+        self.flush().await
+    }
+}
+
 fn get_terminal_params() -> anyhow::Result<TerminalParams> {
     let (cols, rows) = size()?;
     let term = std::env::var("TERM")?;
@@ -28,7 +40,9 @@ fn get_terminal_params() -> anyhow::Result<TerminalParams> {
         row_height: rows as u32,
         term,
         modes: Vec::new(),
-        username: whoami::username()
+        username: whoami::username(),
+        key_fingerprint: None,
+        peer_ip: None
     })
 }
 
@@ -39,9 +53,10 @@ async fn create_input_receiver() -> Receiver<TerminalCode> {
 
     tokio::spawn(async move {
         let mut buf = Vec::<u8>::new();
+        let mut decoder = TerminalDecoder::new();
         loop {
             stdin().read_buf(&mut buf).await.unwrap();
-            for code in channel_data_to_terminal_codes(buf.as_slice()) {
+            for code in decoder.decode(buf.as_slice()) {
                 tx.send(code).await.unwrap()
             }
             buf.clear();
@@ -51,56 +66,358 @@ async fn create_input_receiver() -> Receiver<TerminalCode> {
     rx
 }
 
-pub fn channel_data_to_terminal_codes(data: &[u8]) -> Vec<TerminalCode> {
-    let mut result = Vec::new();
+/// Turns raw terminal input into [`TerminalCode`]s. Input can arrive split at arbitrary points
+/// (e.g. across SSH packets), so any incomplete escape sequence, UTF-8 character or bracketed
+/// paste is kept until the next read.
+///
+/// A lone `ESC` at the end of a read is reported as the Esc key rather than held back, since
+/// that's what a user pressing Esc sends.
+pub struct TerminalDecoder {
+    pending: Vec<u8>
+}
+
+const PASTE_END: &[u8] = b"\x1b[201~";
+// far more than anyone pastes into a form, but a client that never ends its paste can't make
+// the decoder hold on to everything it sends
+const MAX_PASTE_BYTES: usize = 64 * 1024;
 
-    let mut push_msg = |ascii_code: Option<AsciiCode>, raw_bytes: Vec<u8> |
-        result.push(TerminalCode {ascii_code, raw_bytes });
+enum Parsed {
+    Complete { len: usize, ascii_code: Option<AsciiCode>, modifiers: KeyModifiers },
+    Incomplete
+}
 
-    let mut i = 0;
-    while i < data.len() {
-        match data[i] {
-            27 if i + 1 < data.len() && data[i + 1] == 91 => {
-                let start_i = i;
-                i += 2;
+impl Parsed {
+    fn key(len: usize, ascii_code: AsciiCode) -> Self {
+        Self::Complete { len, ascii_code: Some(ascii_code), modifiers: KeyModifiers::default() }
+    }
 
-                let mut command = Vec::new();
+    fn unknown(len: usize) -> Self {
+        Self::Complete { len, ascii_code: None, modifiers: KeyModifiers::default() }
+    }
+}
 
-                while i < data.len() && data[i].is_ascii() {
-                    command.push(data[i]);
-                    i += 1;
+impl TerminalDecoder {
+    pub fn new() -> Self {
+        Self { pending: Vec::new() }
+    }
+
+    pub fn decode(&mut self, input: &[u8]) -> Vec<TerminalCode> {
+        let mut data = std::mem::take(&mut self.pending);
+        data.extend_from_slice(input);
+
+        let mut result = Vec::new();
+
+        let mut i = 0;
+        while i < data.len() {
+            match Self::parse_key(&data[i..]) {
+                Parsed::Complete { len, ascii_code, modifiers } => {
+                    result.push(TerminalCode { ascii_code, modifiers, raw_bytes: Vec::from(&data[i..i + len]) });
+                    i += len;
                 }
+                Parsed::Incomplete => {
+                    self.pending = Vec::from(&data[i..]);
+                    break;
+                }
+            }
+        }
+
+        result
+    }
+
+    fn parse_key(data: &[u8]) -> Parsed {
+        match data[0] {
+            27 => Self::parse_escape(data),
+            _ => Self::parse_plain(data)
+        }
+    }
+
+    /// Anything that isn't an escape sequence: control characters and (possibly multi-byte) text.
+    fn parse_plain(data: &[u8]) -> Parsed {
+        match data[0] {
+            3 => Parsed::key(1, EoT), // ctrl-c
+            8 | 127 => Parsed::key(1, Backspace),
+            9 => Parsed::key(1, Tab),
+            13 => Parsed::key(1, Enter),
+            0 => Parsed::key(1, Ctrl(' ')),
+            1..=26 => Parsed::key(1, Ctrl((b'a' + data[0] - 1) as char)),
+            27..=31 => Parsed::key(1, Ctrl((b'@' + data[0]) as char)),
+            32..=126 => Parsed::key(1, Char(data[0] as char)),
+            lead => {
+                let len = utf8_sequence_length(lead);
+                let available = &data[..min(len, data.len())];
 
-                if i < data.len() {
-                    command.push(data[i]);
-                    i += 1
+                if available.len() < len && available[1..].iter().all(|&b| is_continuation_byte(b)) {
+                    // the rest of the character hasn't arrived yet
+                    return Parsed::Incomplete;
                 }
 
-                match command.as_slice() {
-                    [65] => push_msg(Some(ArrowUp), Vec::from(&data[start_i..i])),
-                    [66] => push_msg(Some(ArrowDown), Vec::from(&data[start_i..i])),
-                    _ => push_msg(None, Vec::from(&data[start_i..i]))
+                match str::from_utf8(available).ok().and_then(|s| s.chars().next()) {
+                    Some(c) => Parsed::key(len, Char(c)),
+                    None => Parsed::unknown(1)
                 }
             }
-            127 => {
-                push_msg(Some(Backspace), Vec::from(&[data[i]]));
-                i += 1
-            }
-            0..=31 => {
-                match data[i] {
-                    3 => push_msg(Some(EoT), vec![data[i]]), // ctrl-c
-                    8 => push_msg(Some(Backspace), vec![data[i]]),
-                    13 => push_msg(Some(Enter), vec![data[i]]),
-                    _ => {}
+        }
+    }
+
+    fn parse_escape(data: &[u8]) -> Parsed {
+        match data.get(1) {
+            None | Some(27) => Parsed::key(1, Esc),
+            Some(b'[') => Self::parse_csi(data),
+            Some(b'O') => Self::parse_ss3(data),
+            Some(_) => match Self::parse_plain(&data[1..]) {
+                Parsed::Complete { len, ascii_code, mut modifiers } => {
+                    let ascii_code = match ascii_code {
+                        Some(Char(c)) => Some(Alt(c)),
+                        other => {
+                            modifiers.alt = true;
+                            other
+                        }
+                    };
+                    Parsed::Complete { len: len + 1, ascii_code, modifiers }
                 }
-                i += 1;
+                Parsed::Incomplete => Parsed::Incomplete
+            }
+        }
+    }
+
+    /// `ESC [ <params> <final>`, e.g. `ESC [ A` or `ESC [ 1 ; 5 C`
+    fn parse_csi(data: &[u8]) -> Parsed {
+        let mut i = 2;
+        while i < data.len() && (0x20..=0x3F).contains(&data[i]) {
+            i += 1;
+        }
+
+        let Some(&final_byte) = data.get(i) else {
+            return Parsed::Incomplete;
+        };
+
+        if !(0x40..=0x7E).contains(&final_byte) {
+            // not a valid sequence; drop what we've seen and carry on from the offending byte
+            return Parsed::unknown(i);
+        }
+
+        let len = i + 1;
+        let params: Vec<u32> = str::from_utf8(&data[2..i]).unwrap_or_default()
+            .split(';')
+            .map(|param| param.parse().unwrap_or(1))
+            .collect();
+        let modifiers = modifiers_from_param(params.get(1).copied());
+
+        let ascii_code = match (final_byte, params[0]) {
+            (b'~', 200) => return Self::parse_paste(data, len),
+            (b'A', _) => ArrowUp,
+            (b'B', _) => ArrowDown,
+            (b'C', _) => ArrowRight,
+            (b'D', _) => ArrowLeft,
+            (b'H', _) => Home,
+            (b'F', _) => End,
+            (b'Z', _) => BackTab,
+            (b'P'..=b'S', _) => F(final_byte - b'P' + 1),
+            (b'~', 1 | 7) => Home,
+            (b'~', 2) => Insert,
+            (b'~', 3) => Delete,
+            (b'~', 4 | 8) => End,
+            (b'~', 5) => PageUp,
+            (b'~', 6) => PageDown,
+            (b'~', n @ 11..=15) => F(n as u8 - 10),
+            (b'~', n @ 17..=21) => F(n as u8 - 11),
+            (b'~', n @ 23..=24) => F(n as u8 - 12),
+            _ => return Parsed::unknown(len)
+        };
+
+        Parsed::Complete { len, ascii_code: Some(ascii_code), modifiers }
+    }
+
+    /// `ESC O <final>`, sent for arrows/Home/End in application cursor mode and for F1-F4
+    fn parse_ss3(data: &[u8]) -> Parsed {
+        let Some(&final_byte) = data.get(2) else {
+            return Parsed::Incomplete;
+        };
+
+        match final_byte {
+            b'A' => Parsed::key(3, ArrowUp),
+            b'B' => Parsed::key(3, ArrowDown),
+            b'C' => Parsed::key(3, ArrowRight),
+            b'D' => Parsed::key(3, ArrowLeft),
+            b'H' => Parsed::key(3, Home),
+            b'F' => Parsed::key(3, End),
+            b'P'..=b'S' => Parsed::key(3, F(final_byte - b'P' + 1)),
+            _ => Parsed::unknown(3)
+        }
+    }
+
+    /// Bracketed paste: everything between `ESC [ 200 ~` and `ESC [ 201 ~` is one [`Paste`].
+    /// A paste that goes on for more than [`MAX_PASTE_BYTES`] is cut off there, and whatever
+    /// follows is read as ordinary input.
+    fn parse_paste(data: &[u8], start: usize) -> Parsed {
+        let Some(end) = data[start..].windows(PASTE_END.len()).position(|window| window == PASTE_END) else {
+            if data.len() - start <= MAX_PASTE_BYTES {
+                return Parsed::Incomplete;
             }
-            _ => {
-                push_msg(Some(Char(data[i])), vec![data[i]]);
-                i += 1;
+
+            // don't split a character in two
+            let mut end = start + MAX_PASTE_BYTES;
+            while end > start && is_continuation_byte(data[end]) {
+                end -= 1;
             }
+
+            let text = String::from_utf8_lossy(&data[start..end]).into_owned();
+            return Parsed::key(end, Paste(text));
+        };
+
+        let text = String::from_utf8_lossy(&data[start..start + end]).into_owned();
+        Parsed::key(start + end + PASTE_END.len(), Paste(text))
+    }
+}
+
+fn modifiers_from_param(param: Option<u32>) -> KeyModifiers {
+    // xterm encodes modifiers as 1 + (shift | alt << 1 | ctrl << 2)
+    let bits = param.unwrap_or(1).saturating_sub(1);
+
+    KeyModifiers {
+        shift: bits & 1 != 0,
+        alt: bits & 2 != 0,
+        ctrl: bits & 4 != 0
+    }
+}
+
+fn utf8_sequence_length(lead: u8) -> usize {
+    match lead {
+        0xC2..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF4 => 4,
+        _ => 1
+    }
+}
+
+fn is_continuation_byte(byte: u8) -> bool {
+    (0x80..=0xBF).contains(&byte)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NONE: KeyModifiers = KeyModifiers { shift: false, alt: false, ctrl: false };
+    const SHIFT: KeyModifiers = KeyModifiers { shift: true, alt: false, ctrl: false };
+    const ALT: KeyModifiers = KeyModifiers { shift: false, alt: true, ctrl: false };
+    const CTRL: KeyModifiers = KeyModifiers { shift: false, alt: false, ctrl: true };
+
+    fn decode_all(chunks: &[&[u8]]) -> Vec<(Option<AsciiCode>, KeyModifiers)> {
+        let mut decoder = TerminalDecoder::new();
+        chunks.iter()
+            .flat_map(|chunk| decoder.decode(chunk))
+            .map(|code| (code.ascii_code, code.modifiers))
+            .collect()
+    }
+
+    #[test]
+    fn decodes_single_keys() {
+        let cases: &[(&[u8], AsciiCode, KeyModifiers)] = &[
+            (b"a", Char('a'), NONE),
+            (b"\r", Enter, NONE),
+            (b"\x7f", Backspace, NONE),
+            (b"\x08", Backspace, NONE),
+            (b"\t", Tab, NONE),
+            (b"\x03", EoT, NONE),
+            (b"\x04", Ctrl('d'), NONE),
+            (b"\x1b", Esc, NONE),
+            (b"\x1b[A", ArrowUp, NONE),
+            (b"\x1b[B", ArrowDown, NONE),
+            (b"\x1b[C", ArrowRight, NONE),
+            (b"\x1b[D", ArrowLeft, NONE),
+            (b"\x1bOA", ArrowUp, NONE),
+            (b"\x1bOD", ArrowLeft, NONE),
+            (b"\x1b[H", Home, NONE),
+            (b"\x1b[F", End, NONE),
+            (b"\x1bOH", Home, NONE),
+            (b"\x1b[1~", Home, NONE),
+            (b"\x1b[4~", End, NONE),
+            (b"\x1b[2~", Insert, NONE),
+            (b"\x1b[3~", Delete, NONE),
+            (b"\x1b[5~", PageUp, NONE),
+            (b"\x1b[6~", PageDown, NONE),
+            (b"\x1bOP", F(1), NONE),
+            (b"\x1bOS", F(4), NONE),
+            (b"\x1b[15~", F(5), NONE),
+            (b"\x1b[17~", F(6), NONE),
+            (b"\x1b[21~", F(10), NONE),
+            (b"\x1b[24~", F(12), NONE),
+            (b"\x1b[Z", BackTab, NONE),
+            (b"\x1b[1;5C", ArrowRight, CTRL),
+            (b"\x1b[1;2A", ArrowUp, SHIFT),
+            (b"\x1b[3;3~", Delete, ALT),
+            (b"\x1bx", Alt('x'), NONE),
+            (b"\x1b\x7f", Backspace, ALT),
+            ("é".as_bytes(), Char('é'), NONE),
+            ("张".as_bytes(), Char('张'), NONE),
+            ("🦀".as_bytes(), Char('🦀'), NONE),
+            (b"\x1b[200~hello\r\nworld\x1b[201~", Paste("hello\r\nworld".to_string()), NONE),
+        ];
+
+        for (input, ascii_code, modifiers) in cases {
+            assert_eq!(decode_all(&[input]), vec![(Some(ascii_code.clone()), *modifiers)], "input: {:?}", input);
+        }
+    }
+
+    #[test]
+    fn decodes_sequences_split_across_reads() {
+        let cases: &[(&[&[u8]], Vec<AsciiCode>)] = &[
+            (&[b"\x1b[", b"A"], vec![ArrowUp]),
+            (&[b"\x1bO", b"B"], vec![ArrowDown]),
+            (&[b"\x1b[1;", b"5", b"D"], vec![ArrowLeft]),
+            (&[b"\x1b[200~ab", b"c\x1b[2", b"01~"], vec![Paste("abc".to_string())]),
+            (&[&"é".as_bytes()[..1], &"é".as_bytes()[1..]], vec![Char('é')]),
+            (&[&"张".as_bytes()[..2], &"张".as_bytes()[2..], b"x"], vec![Char('张'), Char('x')]),
+        ];
+
+        for (chunks, expected) in cases {
+            let decoded: Vec<AsciiCode> = decode_all(chunks).into_iter().filter_map(|(code, _)| code).collect();
+            assert_eq!(&decoded, expected, "chunks: {:?}", chunks);
         }
     }
 
-    result
-}
\ No newline at end of file
+    #[test]
+    fn cuts_off_pastes_that_never_end() {
+        let long = "a".repeat(MAX_PASTE_BYTES);
+        let crabs = "🦀".repeat(MAX_PASTE_BYTES / 4);
+        let cases: Vec<(Vec<&[u8]>, Vec<AsciiCode>)> = vec![
+            (vec![b"\x1b[200~", long.as_bytes(), b"\x1b[201~"], vec![Paste(long.clone())]),
+            (vec![b"\x1b[200~", long.as_bytes(), b"b\r"], vec![Paste(long.clone()), Char('b'), Enter]),
+            (vec![b"\x1b[200~", long.as_bytes(), b"bc", b"\x1b[201~x"], vec![Paste(long.clone()), Char('b'), Char('c'), Char('x')]),
+            // the cut doesn't land in the middle of a character
+            (vec![b"\x1b[200~x", crabs.as_bytes()], vec![Paste(format!("x{}", &crabs[..crabs.len() - 4])), Char('🦀')]),
+        ];
+
+        for (chunks, expected) in cases {
+            let decoded: Vec<AsciiCode> = decode_all(&chunks).into_iter().filter_map(|(code, _)| code).collect();
+            assert!(decoded == expected, "{} chunks decoded to {} codes", chunks.len(), decoded.len());
+        }
+    }
+
+    #[test]
+    fn decodes_mixed_input() {
+        assert_eq!(
+            decode_all(&[b"hi\x1b[Ax\x1b\r"]),
+            vec![
+                (Some(Char('h')), NONE),
+                (Some(Char('i')), NONE),
+                (Some(ArrowUp), NONE),
+                (Some(Char('x')), NONE),
+                (Some(Enter), ALT),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_raw_bytes_of_unknown_sequences() {
+        let mut decoder = TerminalDecoder::new();
+        let codes = decoder.decode(b"\x1b[?1;2c\xff");
+
+        assert_eq!(codes.len(), 2);
+        assert_eq!(codes[0].ascii_code, None);
+        assert_eq!(codes[0].raw_bytes, b"\x1b[?1;2c");
+        assert_eq!(codes[1].ascii_code, None);
+        assert_eq!(codes[1].raw_bytes, b"\xff");
+    }
+}